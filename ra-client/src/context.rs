@@ -1,20 +1,53 @@
 use crate::error::ClientRaError;
+#[cfg(feature = "sim")]
+use crate::sim::{SimAesmClient as AesmClient, SimQuoteInfo as QuoteInfo};
 use crate::ClientRaResult;
-use aesm_client::{AesmClient, QuoteInfo, QuoteType};
-use ra_common::msg::{Gid, Quote, RaMsg0, RaMsg1, RaMsg2, RaMsg3, RaMsg4};
-use sgx_crypto::cmac::MacTag;
+use aesm_client::QuoteType;
+#[cfg(not(feature = "sim"))]
+use aesm_client::{AesmClient, QuoteInfo};
+use ra_common::framing;
+use ra_common::msg::{
+    EnclavePublicKey, Gid, Msg3Mac, QeTargetInfo, Quote, QuoteNonce, QuoteRequest, QuoteResponse,
+    RaMsg0, RaMsg1, RaMsg2, RaMsg3, RaMsg4,
+};
 use sgx_crypto::key_exchange::DHKEPublicKey;
-use sgx_isa::Report;
 use std::convert::TryInto;
 use std::io::{Read, Write};
-use std::mem::size_of;
 
+/// Client before the protocol starts. `do_attestation` runs the whole protocol over the streams
+/// to the enclave and the SP; otherwise, `get_msg_1` and the steps of the returned states can be
+/// driven over any transport.
 pub struct ClientRaContext {
     pub aesm_client: AesmClient,
     pub quote_info: QuoteInfo,
-    pub g_a: Option<DHKEPublicKey>,
 }
 
+/// Client that has sent MSG0 and MSG1 and is waiting for MSG2 from the SP.
+pub struct ClientAwaitingMsg2 {
+    aesm_client: AesmClient,
+    g_a: DHKEPublicKey,
+}
+
+/// Client that has relayed MSG2 and QE's target info to the enclave, and is waiting for the
+/// enclave's report to get quoted.
+pub struct ClientAwaitingQuoteRequest {
+    aesm_client: AesmClient,
+    g_a: DHKEPublicKey,
+    spid: Vec<u8>,
+    sig_rl: Vec<u8>,
+    quote_type: QuoteType,
+}
+
+/// Client that has sent the quote to the enclave and is waiting for the MAC of MSG3.
+pub struct ClientAwaitingMsg3Mac {
+    g_a: DHKEPublicKey,
+    nonce: QuoteNonce,
+    quote: Quote,
+}
+
+/// Client that has sent MSG3 and is waiting for MSG4 from the SP.
+pub struct ClientAwaitingMsg4;
+
 impl ClientRaContext {
     pub fn init() -> ClientRaResult<Self> {
         let aesm_client = AesmClient::new();
@@ -22,12 +55,11 @@ impl ClientRaContext {
         Ok(Self {
             aesm_client,
             quote_info,
-            g_a: None,
         })
     }
 
     pub fn do_attestation(
-        mut self,
+        self,
         mut enclave_stream: &mut (impl Read + Write),
         mut sp_stream: &mut (impl Read + Write),
     ) -> ClientRaResult<()> {
@@ -36,59 +68,55 @@ impl ClientRaContext {
             eprintln!("MSG0 generated");
         }
 
-        bincode::serialize_into(&mut sp_stream, &msg0)?;
-        sp_stream.flush()?;
+        framing::write_msg(&mut sp_stream, &msg0)?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG0 sent");
         }
 
-        let msg1 = self.get_msg_1(enclave_stream);
+        let public_key: EnclavePublicKey = framing::read_msg(&mut enclave_stream)?;
+        let (msg1, context) = self.get_msg_1(public_key)?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG1 generated");
         }
 
-        bincode::serialize_into(&mut sp_stream, &msg1)?;
-        sp_stream.flush()?;
+        framing::write_msg(&mut sp_stream, &msg1)?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG1 sent");
         }
 
-        let msg2: RaMsg2 = bincode::deserialize_from(&mut sp_stream)?;
+        let msg2: RaMsg2 = framing::read_msg(&mut sp_stream)?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG2 received");
         }
 
-        let msg3 = self.process_msg_2(msg2, enclave_stream)?;
+        // Get a Quote of the enclave
+        let (msg2, target_info, context) = context.process_msg_2(msg2)?;
+        framing::write_msg(&mut enclave_stream, &msg2)?;
+        framing::write_msg(&mut enclave_stream, &target_info)?;
+        let quote_request: QuoteRequest = framing::read_msg(&mut enclave_stream)?;
+        let (quote_response, context) = context.process_quote_request(quote_request)?;
+        framing::write_msg(&mut enclave_stream, &quote_response)?;
+
+        // Read MAC for msg3 from enclave
+        let msg3_mac: Msg3Mac = framing::read_msg(&mut enclave_stream)?;
+        let (msg3, context) = context.process_msg3_mac(msg3_mac);
         if cfg!(feature = "verbose") {
             eprintln!("MSG3 generated");
         }
 
-        bincode::serialize_into(&mut sp_stream, &msg3)?;
-        sp_stream.flush()?;
+        framing::write_msg(&mut sp_stream, &msg3)?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG3 sent");
         }
 
-        let msg4: RaMsg4 = bincode::deserialize_from(&mut sp_stream)?;
+        let msg4: RaMsg4 = framing::read_msg(&mut sp_stream)?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG4 received");
         }
 
-        bincode::serialize_into(&mut enclave_stream, &msg4).unwrap();
-        sp_stream.flush()?;
-
-        if !msg4.is_enclave_trusted {
-            return Err(ClientRaError::EnclaveNotTrusted);
-        }
-        match msg4.is_pse_manifest_trusted {
-            Some(t) => {
-                if !t {
-                    return Err(ClientRaError::PseNotTrusted);
-                }
-            }
-            None => {}
-        }
-        Ok(())
+        let (msg4, result) = context.process_msg_4(msg4);
+        framing::write_msg(&mut enclave_stream, &msg4)?;
+        result
     }
 
     /// ExGID = 0 means IAS will be used for remote attestation. This function only
@@ -97,66 +125,337 @@ impl ClientRaContext {
         RaMsg0 { exgid: 0 }
     }
 
-    pub fn get_msg_1(&mut self, enclave_stream: &mut (impl Read + Write)) -> RaMsg1 {
-        let g_a: DHKEPublicKey = bincode::deserialize_from(enclave_stream).unwrap();
-        let gid: Gid = self.quote_info.gid().try_into().unwrap();
-        self.g_a = Some(g_a.clone());
-        RaMsg1 { gid, g_a }
+    pub fn get_msg_1(
+        self,
+        public_key: EnclavePublicKey,
+    ) -> ClientRaResult<(RaMsg1, ClientAwaitingMsg2)> {
+        let gid: Gid = self
+            .quote_info
+            .gid()
+            .try_into()
+            .map_err(|_| ClientRaError::InvalidGid)?;
+        let g_a = public_key.g_a;
+        let context = ClientAwaitingMsg2 {
+            aesm_client: self.aesm_client,
+            g_a: g_a.clone(),
+        };
+        Ok((RaMsg1 { gid, g_a }, context))
     }
+}
 
+impl ClientAwaitingMsg2 {
+    /// Returns MSG2 and QE's target info, both to be relayed to the enclave.
     pub fn process_msg_2(
-        &mut self,
+        self,
         msg2: RaMsg2,
-        mut enclave_stream: &mut (impl Read + Write),
-    ) -> ClientRaResult<RaMsg3> {
-        bincode::serialize_into(&mut enclave_stream, &msg2)?;
-        enclave_stream.flush()?;
-
-        let sig_rl = match msg2.sig_rl {
+    ) -> ClientRaResult<(RaMsg2, QeTargetInfo, ClientAwaitingQuoteRequest)> {
+        let sig_rl = match msg2.sig_rl.as_ref() {
             Some(sig_rl) => sig_rl.to_owned(),
             None => Vec::with_capacity(0),
         };
         let spid = (&msg2.spid[..]).to_owned();
+        let quote_type = match msg2.quote_type {
+            0 => QuoteType::Unlinkable,
+            1 => QuoteType::Linkable,
+            t => return Err(ClientRaError::UnknownQuoteType(t)),
+        };
 
-        // Get a Quote and send it to enclave to sign
-        let quote = Self::get_quote(&self.aesm_client, spid, sig_rl, enclave_stream)?;
+        // The enclave needs QE's target info for local attestation with QE
+        let quote_info = self.aesm_client.init_quote()?;
+        let target_info = QeTargetInfo {
+            target_info: quote_info.target_info().to_vec(),
+        };
 
-        // Read MAC for msg3 from enclave
-        let mut mac = [0u8; size_of::<MacTag>()];
-        enclave_stream.read_exact(&mut mac)?;
+        let context = ClientAwaitingQuoteRequest {
+            aesm_client: self.aesm_client,
+            g_a: self.g_a,
+            spid,
+            sig_rl,
+            quote_type,
+        };
+        Ok((msg2, target_info, context))
+    }
+}
 
-        Ok(RaMsg3 {
-            g_a: self.g_a.take().unwrap(),
-            mac,
-            ps_sec_prop: None,
+impl ClientAwaitingQuoteRequest {
+    /// Gets a quote and QE report of the enclave's report from QE, to be sent to the enclave.
+    pub fn process_quote_request(
+        self,
+        quote_request: QuoteRequest,
+    ) -> ClientRaResult<(QuoteResponse, ClientAwaitingMsg3Mac)> {
+        let QuoteRequest { report, nonce } = quote_request;
+        let _quote = self.aesm_client.get_quote(
+            report,
+            self.spid,
+            self.sig_rl,
+            self.quote_type,
+            nonce.to_vec(),
+        )?;
+        let quote: Quote = _quote.quote().to_vec();
+        let quote_response = QuoteResponse {
+            quote: quote.clone(),
+            qe_report: _quote.qe_report().to_vec(),
+        };
+        let context = ClientAwaitingMsg3Mac {
+            g_a: self.g_a,
+            nonce,
             quote,
-        })
+        };
+        Ok((quote_response, context))
     }
+}
 
-    /// Get a Quote and send it to enclave to sign
-    pub fn get_quote(
-        aesm_client: &AesmClient,
-        spid: Vec<u8>,
-        sig_rl: Vec<u8>,
-        enclave_stream: &mut (impl Read + Write),
-    ) -> ClientRaResult<Quote> {
-        let quote_info = aesm_client.init_quote()?;
+impl ClientAwaitingMsg3Mac {
+    /// Returns MSG3 with the MAC computed by the enclave.
+    pub fn process_msg3_mac(self, msg3_mac: Msg3Mac) -> (RaMsg3, ClientAwaitingMsg4) {
+        let msg3 = RaMsg3 {
+            g_a: self.g_a,
+            mac: msg3_mac.mac,
+            ps_sec_prop: None,
+            nonce: self.nonce,
+            quote: self.quote,
+        };
+        (msg3, ClientAwaitingMsg4)
+    }
+}
+
+impl ClientAwaitingMsg4 {
+    /// Returns MSG4, which must be relayed to the enclave whether or not the enclave is trusted,
+    /// and the result of the attestation.
+    pub fn process_msg_4(self, msg4: RaMsg4) -> (RaMsg4, ClientRaResult<()>) {
+        let result = if !msg4.is_enclave_trusted {
+            Err(ClientRaError::EnclaveNotTrusted)
+        } else if msg4.is_pse_manifest_trusted == Some(false) {
+            Err(ClientRaError::PseNotTrusted)
+        } else {
+            Ok(())
+        };
+        (msg4, result)
+    }
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use ra_common::framing::{FramingError, MsgType, PROTOCOL_VERSION};
+    use ra_common::sim;
+    use std::io::{self, Cursor};
+
+    const SPID: [u8; 16] = [0x5a; 16];
+
+    /// Stream that reads scripted input and collects the output.
+    struct Pipe {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Pipe {
+        fn new(input: Vec<u8>) -> Self {
+            Self {
+                input: Cursor::new(input),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs the client with `input` from the enclave, where it expects the enclave's public key.
+    fn attest_with_enclave_input(input: Vec<u8>) -> ClientRaError {
+        let context = ClientRaContext::init().unwrap();
+        let mut enclave_stream = Pipe::new(input);
+        let mut sp_stream = Pipe::new(Vec::new());
+        context
+            .do_attestation(&mut enclave_stream, &mut sp_stream)
+            .unwrap_err()
+    }
+
+    fn frame(msg_type: u8, len: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = PROTOCOL_VERSION.to_le_bytes().to_vec();
+        frame.push(msg_type);
+        frame.extend_from_slice(&len.to_le_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn msg2(quote_type: u16) -> RaMsg2 {
+        RaMsg2 {
+            g_b: vec![4; 65],
+            spid: SPID,
+            quote_type,
+            sign_gb_ga: vec![0; 256],
+            mac: [0; 16],
+            sig_rl: None,
+        }
+    }
+
+    fn awaiting_msg2() -> ClientAwaitingMsg2 {
+        let public_key = EnclavePublicKey { g_a: vec![4; 65] };
+        let (_, context) = ClientRaContext::init()
+            .unwrap()
+            .get_msg_1(public_key)
+            .unwrap();
+        context
+    }
+
+    fn awaiting_quote_request() -> ClientAwaitingQuoteRequest {
+        let (_, _, context) = awaiting_msg2().process_msg_2(msg2(0)).unwrap();
+        context
+    }
+
+    fn enclave_report() -> Vec<u8> {
+        sim::report(&sim::SIM_ENCLAVE, &[0; 64]).unwrap()
+    }
+
+    fn msg4(is_enclave_trusted: bool, is_pse_manifest_trusted: Option<bool>) -> RaMsg4 {
+        RaMsg4 {
+            is_enclave_trusted,
+            is_pse_manifest_trusted,
+            pib: None,
+            ias_report: None,
+            mac: [0; 16],
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_frame() {
+        let mut input = Vec::new();
+        framing::write_msg(&mut input, &EnclavePublicKey { g_a: vec![4; 65] }).unwrap();
+        input.truncate(input.len() - 1);
+
+        match attest_with_enclave_input(input) {
+            ClientRaError::Framing(FramingError::IO(e))
+                if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_message_type() {
+        let mut input = Vec::new();
+        framing::write_msg(&mut input, &Msg3Mac { mac: [0; 16] }).unwrap();
+
+        match attest_with_enclave_input(input) {
+            ClientRaError::Framing(FramingError::UnexpectedMessageType {
+                expected: MsgType::EnclavePublicKey,
+                received: 20,
+            }) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
 
-        // Get report for local attestation with QE from enclave
-        enclave_stream.write_all(quote_info.target_info())?;
-        enclave_stream.flush()?;
-        let mut report = vec![0u8; Report::UNPADDED_SIZE];
-        enclave_stream.read_exact(&mut report[..])?;
-
-        // Get a quote and QE report from QE and send them to enclave
-        let nonce = vec![0u8; 16]; // TODO change this
-        let _quote = aesm_client.get_quote(report, spid, sig_rl, QuoteType::Linkable, nonce)?;
-        enclave_stream.write_all(_quote.quote())?;
-        enclave_stream.write_all(_quote.qe_report())?;
-        enclave_stream.flush()?;
-
-        let mut quote = [0u8; size_of::<Quote>()];
-        quote.copy_from_slice(_quote.quote());
-        Ok(quote)
+    #[test]
+    fn rejects_oversized_length_header() {
+        match attest_with_enclave_input(frame(16, u32::MAX, &[])) {
+            ClientRaError::Framing(FramingError::MessageTooLarge {
+                size: 0xffff_ffff, ..
+            }) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_garbage_payload() {
+        match attest_with_enclave_input(frame(16, 4, &[0xff; 4])) {
+            ClientRaError::Framing(FramingError::Serialization(_)) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_quote_type() {
+        match awaiting_msg2().process_msg_2(msg2(2)).err() {
+            Some(ClientRaError::UnknownQuoteType(2)) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn relays_msg2_and_qe_target_info() {
+        let (relayed, target_info, _) = awaiting_msg2().process_msg_2(msg2(1)).unwrap();
+        assert_eq!(relayed.quote_type, 1);
+        assert_eq!(target_info.target_info, sim::target_info(&sim::SIM_QE));
+    }
+
+    #[test]
+    fn quotes_enclave_report() {
+        let quote_request = QuoteRequest {
+            report: enclave_report(),
+            nonce: [7; 16],
+        };
+        let (quote_response, context) = awaiting_quote_request()
+            .process_quote_request(quote_request)
+            .unwrap();
+        let (msg3, _) = context.process_msg3_mac(Msg3Mac { mac: [1; 16] });
+        assert_eq!(msg3.quote, quote_response.quote);
+        assert_eq!(msg3.nonce, [7; 16]);
+        assert_eq!(msg3.mac, [1; 16]);
+    }
+
+    #[test]
+    fn rejects_short_or_tampered_report() {
+        let mut report = enclave_report();
+        report.pop();
+        let quote_request = QuoteRequest {
+            report,
+            nonce: [7; 16],
+        };
+        match awaiting_quote_request()
+            .process_quote_request(quote_request)
+            .err()
+        {
+            Some(ClientRaError::Simulation(_)) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let mut report = enclave_report();
+        // MRENCLAVE
+        report[64] ^= 1;
+        let quote_request = QuoteRequest {
+            report,
+            nonce: [7; 16],
+        };
+        match awaiting_quote_request()
+            .process_quote_request(quote_request)
+            .err()
+        {
+            Some(ClientRaError::Simulation(_)) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn relays_msg4_whatever_the_result() {
+        let (relayed, result) = ClientAwaitingMsg4.process_msg_4(msg4(true, Some(true)));
+        assert!(relayed.is_enclave_trusted);
+        result.unwrap();
+
+        let (relayed, result) = ClientAwaitingMsg4.process_msg_4(msg4(false, None));
+        assert!(!relayed.is_enclave_trusted);
+        match result {
+            Err(ClientRaError::EnclaveNotTrusted) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        let (relayed, result) = ClientAwaitingMsg4.process_msg_4(msg4(true, Some(false)));
+        assert_eq!(relayed.is_pse_manifest_trusted, Some(false));
+        match result {
+            Err(ClientRaError::PseNotTrusted) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
     }
 }
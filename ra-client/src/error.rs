@@ -1,9 +1,14 @@
 #[derive(Debug)]
 pub enum ClientRaError {
     IO(IOError),
+    Framing(ra_common::framing::FramingError),
     Aesm(aesm_client::Error),
     EnclaveNotTrusted,
     PseNotTrusted,
+    UnknownQuoteType(u16),
+    InvalidGid,
+    #[cfg(feature = "sim")]
+    Simulation(sgx_crypto::error::CryptoError),
 }
 
 #[derive(Debug)]
@@ -12,6 +17,12 @@ pub enum IOError {
     StdIo(std::io::Error),
 }
 
+impl std::convert::From<ra_common::framing::FramingError> for ClientRaError {
+    fn from(e: ra_common::framing::FramingError) -> Self {
+        Self::Framing(e)
+    }
+}
+
 impl std::convert::From<aesm_client::Error> for ClientRaError {
     fn from(e: aesm_client::Error) -> Self {
         Self::Aesm(e)
@@ -1,5 +1,7 @@
 mod context;
 mod error;
+#[cfg(feature = "sim")]
+mod sim;
 
 pub use crate::context::*;
 pub use crate::error::*;
@@ -0,0 +1,245 @@
+//! Client relay daemon: accepts connections from local enclaves, opens a connection to the SP of
+//! each enclave, runs the attestation between them, and then forwards bytes both ways so that
+//! the enclave and the SP can keep talking, e.g. over TLS-PSK, on the same connections. Logs are
+//! written to stderr as logfmt lines.
+use ra_client::ClientRaContext;
+use ra_common::log;
+use serde::Deserialize;
+use std::fs::File;
+use std::io;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const USAGE: &str = "Usage: ra-client --config <relay.json>";
+
+#[derive(Deserialize)]
+struct RelayConfig {
+    enclaves: Vec<EnclaveConfig>,
+}
+
+#[derive(Deserialize, Clone)]
+struct EnclaveConfig {
+    /// Used in logs
+    name: String,
+    /// Address that the enclave connects to
+    listen_address: String,
+    /// Address of the enclave's SP
+    sp_address: String,
+    /// Seconds to wait for any message from either side during the attestation
+    #[serde(default = "default_attestation_timeout")]
+    attestation_timeout: u64,
+    /// Connections relayed at once, each of which takes two threads
+    #[serde(default = "default_max_connections")]
+    max_connections: usize,
+}
+
+fn default_attestation_timeout() -> u64 {
+    60
+}
+
+fn default_max_connections() -> usize {
+    64
+}
+
+/// Frees a connection slot of an enclave when dropped.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let config_path = match (args.next().as_deref(), args.next()) {
+        (Some("--config"), Some(path)) => path,
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let config: RelayConfig = match File::open(&config_path)
+        .map_err(|e| e.to_string())
+        .and_then(|f| serde_json::from_reader(f).map_err(|e| e.to_string()))
+    {
+        Ok(config) => config,
+        Err(e) => {
+            log!("error", "can't read config", path = config_path, error = e);
+            std::process::exit(1);
+        }
+    };
+
+    let listeners = config
+        .enclaves
+        .into_iter()
+        .map(|enclave| thread::spawn(move || listen(enclave)))
+        .collect::<Vec<_>>();
+    for listener in listeners {
+        let _ = listener.join();
+    }
+}
+
+fn listen(enclave: EnclaveConfig) {
+    let listener = match TcpListener::bind(&enclave.listen_address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log!(
+                "error",
+                "can't listen",
+                enclave = enclave.name,
+                address = enclave.listen_address,
+                error = e.to_string()
+            );
+            return;
+        }
+    };
+    log!(
+        "info",
+        "listening",
+        enclave = enclave.name,
+        address = enclave.listen_address
+    );
+    let connections = Arc::new(AtomicUsize::new(0));
+    for enclave_stream in listener.incoming() {
+        match enclave_stream {
+            Ok(enclave_stream) => {
+                if connections.fetch_add(1, Ordering::SeqCst) >= enclave.max_connections {
+                    connections.fetch_sub(1, Ordering::SeqCst);
+                    log!(
+                        "warn",
+                        "too many connections, dropping enclave",
+                        enclave = enclave.name
+                    );
+                    continue;
+                }
+                let slot = ConnectionSlot(connections.clone());
+                let enclave = enclave.clone();
+                thread::spawn(move || {
+                    relay(enclave, enclave_stream);
+                    drop(slot);
+                });
+            }
+            Err(e) => log!(
+                "warn",
+                "accept failed",
+                enclave = enclave.name,
+                error = e.to_string()
+            ),
+        }
+    }
+}
+
+fn relay(enclave: EnclaveConfig, mut enclave_stream: TcpStream) {
+    let peer = enclave_stream
+        .peer_addr()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+    log!(
+        "info",
+        "enclave connected",
+        enclave = enclave.name,
+        peer = peer
+    );
+
+    let mut sp_stream = match TcpStream::connect(&enclave.sp_address) {
+        Ok(sp_stream) => sp_stream,
+        Err(e) => {
+            log!(
+                "error",
+                "can't connect to SP",
+                enclave = enclave.name,
+                peer = peer,
+                sp = enclave.sp_address,
+                error = e.to_string()
+            );
+            return;
+        }
+    };
+
+    // Neither side may stall the attestation, but the forwarded session may be idle for long
+    let timeout = Some(Duration::from_secs(enclave.attestation_timeout));
+    if let Err(e) = set_timeouts(&enclave_stream, &sp_stream, timeout) {
+        log!(
+            "error",
+            "can't set timeouts",
+            enclave = enclave.name,
+            peer = peer,
+            error = e.to_string()
+        );
+        return;
+    }
+    let attestation = ClientRaContext::init()
+        .and_then(|context| context.do_attestation(&mut enclave_stream, &mut sp_stream));
+    if let Err(e) = attestation {
+        log!(
+            "warn",
+            "attestation failed",
+            enclave = enclave.name,
+            peer = peer,
+            error = format!("{:?}", e)
+        );
+        return;
+    }
+    log!(
+        "info",
+        "attestation succeeded",
+        enclave = enclave.name,
+        peer = peer
+    );
+
+    let forwarding = set_timeouts(&enclave_stream, &sp_stream, None)
+        .and_then(|_| forward(enclave_stream, sp_stream));
+    if let Err(e) = forwarding {
+        log!(
+            "warn",
+            "forwarding failed",
+            enclave = enclave.name,
+            peer = peer,
+            error = e.to_string()
+        );
+        return;
+    }
+    log!(
+        "info",
+        "session closed",
+        enclave = enclave.name,
+        peer = peer
+    );
+}
+
+fn set_timeouts(
+    enclave_stream: &TcpStream,
+    sp_stream: &TcpStream,
+    timeout: Option<Duration>,
+) -> io::Result<()> {
+    for stream in [enclave_stream, sp_stream].iter() {
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+    }
+    Ok(())
+}
+
+/// Forwards bytes both ways until both sides have closed their write half.
+fn forward(enclave_stream: TcpStream, sp_stream: TcpStream) -> io::Result<()> {
+    let (mut enclave_reader, enclave_writer) = (enclave_stream.try_clone()?, enclave_stream);
+    let (mut sp_reader, sp_writer) = (sp_stream.try_clone()?, sp_stream);
+    let upstream = thread::spawn(move || copy_and_shutdown(&mut enclave_reader, sp_writer));
+    let downstream = copy_and_shutdown(&mut sp_reader, enclave_writer);
+    let upstream = upstream.join().unwrap_or_else(|_| {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "relay thread panicked",
+        ))
+    });
+    downstream.and(upstream)
+}
+
+fn copy_and_shutdown(reader: &mut TcpStream, mut writer: TcpStream) -> io::Result<()> {
+    let result = io::copy(reader, &mut writer).map(|_| ());
+    let _ = writer.shutdown(Shutdown::Write);
+    result
+}
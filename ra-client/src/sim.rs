@@ -0,0 +1,70 @@
+//! Software stand-in for `aesm_client` used by the `sim` feature. It mirrors the parts of the
+//! `AesmClient` API used by `ClientRaContext`, and quotes with `ra_common::sim` instead of the
+//! quoting enclave.
+use crate::error::ClientRaError;
+use crate::ClientRaResult;
+use aesm_client::QuoteType;
+use ra_common::sim;
+
+#[derive(Default)]
+pub struct SimAesmClient;
+
+pub struct SimQuoteInfo {
+    target_info: Vec<u8>,
+    gid: Vec<u8>,
+}
+
+impl SimQuoteInfo {
+    pub fn target_info(&self) -> &[u8] {
+        &self.target_info
+    }
+
+    pub fn gid(&self) -> &[u8] {
+        &self.gid
+    }
+}
+
+pub struct SimQuoteResult {
+    quote: Vec<u8>,
+    qe_report: Vec<u8>,
+}
+
+impl SimQuoteResult {
+    pub fn quote(&self) -> &[u8] {
+        &self.quote
+    }
+
+    pub fn qe_report(&self) -> &[u8] {
+        &self.qe_report
+    }
+}
+
+impl SimAesmClient {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn init_quote(&self) -> ClientRaResult<SimQuoteInfo> {
+        Ok(SimQuoteInfo {
+            target_info: sim::target_info(&sim::SIM_QE),
+            gid: sim::SIM_GID.to_vec(),
+        })
+    }
+
+    pub fn get_quote(
+        &self,
+        report: Vec<u8>,
+        spid: Vec<u8>,
+        _sig_rl: Vec<u8>,
+        quote_type: QuoteType,
+        nonce: Vec<u8>,
+    ) -> ClientRaResult<SimQuoteResult> {
+        let linkable = match quote_type {
+            QuoteType::Linkable => true,
+            QuoteType::Unlinkable => false,
+        };
+        let (quote, qe_report) = sim::quote(&report[..], &spid[..], linkable, &nonce[..])
+            .map_err(ClientRaError::Simulation)?;
+        Ok(SimQuoteResult { quote, qe_report })
+    }
+}
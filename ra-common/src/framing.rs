@@ -0,0 +1,237 @@
+//! Wire framing of the RA messages, and of the messages between the enclave and the client.
+//! Every message is preceded by a header of the protocol version (u16), the message type (u8)
+//! and the payload length (u32), all little-endian, and is rejected before being read if its
+//! payload is longer than the maximum for its type. With the `async` feature, messages can also
+//! be read and written over tokio streams.
+use crate::msg::{
+    EnclavePublicKey, Msg3Mac, QeTargetInfo, QuoteRequest, QuoteResponse, RaMsg0, RaMsg1, RaMsg2,
+    RaMsg3, RaMsg4,
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+use std::io::{Read, Write};
+
+pub const PROTOCOL_VERSION: u16 = 1;
+/// Version (u16), message type (u8) and payload length (u32)
+const HEADER_LEN: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MsgType {
+    Msg0 = 0,
+    Msg1 = 1,
+    Msg2 = 2,
+    Msg3 = 3,
+    Msg4 = 4,
+    EnclavePublicKey = 16,
+    QeTargetInfo = 17,
+    QuoteRequest = 18,
+    QuoteResponse = 19,
+    Msg3Mac = 20,
+}
+
+/// A message that can be framed.
+pub trait Message: Serialize + DeserializeOwned {
+    const TYPE: MsgType;
+    /// Maximum length of the serialized message in bytes
+    const MAX_SIZE: u32;
+}
+
+impl Message for RaMsg0 {
+    const TYPE: MsgType = MsgType::Msg0;
+    const MAX_SIZE: u32 = 64;
+}
+
+impl Message for RaMsg1 {
+    const TYPE: MsgType = MsgType::Msg1;
+    const MAX_SIZE: u32 = 4 * 1024;
+}
+
+/// MSG2 carries the SigRL, which grows with the number of revoked platforms in the group.
+impl Message for RaMsg2 {
+    const TYPE: MsgType = MsgType::Msg2;
+    const MAX_SIZE: u32 = 1024 * 1024;
+}
+
+/// MSG3 carries the quote, whose signature grows with the size of the SigRL.
+impl Message for RaMsg3 {
+    const TYPE: MsgType = MsgType::Msg3;
+    const MAX_SIZE: u32 = 1024 * 1024;
+}
+
+/// MSG4 may carry the IAS report and its certificate chain.
+impl Message for RaMsg4 {
+    const TYPE: MsgType = MsgType::Msg4;
+    const MAX_SIZE: u32 = 64 * 1024;
+}
+
+impl Message for EnclavePublicKey {
+    const TYPE: MsgType = MsgType::EnclavePublicKey;
+    const MAX_SIZE: u32 = 4 * 1024;
+}
+
+impl Message for QeTargetInfo {
+    const TYPE: MsgType = MsgType::QeTargetInfo;
+    const MAX_SIZE: u32 = 1024;
+}
+
+impl Message for QuoteRequest {
+    const TYPE: MsgType = MsgType::QuoteRequest;
+    const MAX_SIZE: u32 = 1024;
+}
+
+/// Same bound as MSG3, which carries the same quote.
+impl Message for QuoteResponse {
+    const TYPE: MsgType = MsgType::QuoteResponse;
+    const MAX_SIZE: u32 = 1024 * 1024;
+}
+
+impl Message for Msg3Mac {
+    const TYPE: MsgType = MsgType::Msg3Mac;
+    const MAX_SIZE: u32 = 64;
+}
+
+#[derive(Debug)]
+pub enum FramingError {
+    IO(std::io::Error),
+    Serialization(std::boxed::Box<bincode::ErrorKind>),
+    VersionMismatched {
+        expected: u16,
+        received: u16,
+    },
+    UnexpectedMessageType {
+        expected: MsgType,
+        received: u8,
+    },
+    MessageTooLarge {
+        msg_type: MsgType,
+        size: u32,
+        max_size: u32,
+    },
+}
+
+impl std::convert::From<std::io::Error> for FramingError {
+    fn from(e: std::io::Error) -> Self {
+        Self::IO(e)
+    }
+}
+
+impl std::convert::From<std::boxed::Box<bincode::ErrorKind>> for FramingError {
+    fn from(e: std::boxed::Box<bincode::ErrorKind>) -> Self {
+        Self::Serialization(e)
+    }
+}
+
+impl fmt::Display for FramingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IO(e) => write!(f, "I/O error: {}", e),
+            Self::Serialization(e) => write!(f, "malformed message: {}", e),
+            Self::VersionMismatched { expected, received } => write!(
+                f,
+                "protocol version mismatched: expected {}, received {}",
+                expected, received
+            ),
+            Self::UnexpectedMessageType { expected, received } => write!(
+                f,
+                "unexpected message type: expected {:?}, received {}",
+                expected, received
+            ),
+            Self::MessageTooLarge {
+                msg_type,
+                size,
+                max_size,
+            } => write!(
+                f,
+                "{:?} of {} bytes exceeds the maximum of {} bytes",
+                msg_type, size, max_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FramingError {}
+
+pub fn write_msg<M: Message>(stream: &mut impl Write, msg: &M) -> Result<(), FramingError> {
+    let frame = encode_frame(msg)?;
+    stream.write_all(&frame[..])?;
+    stream.flush()?;
+    Ok(())
+}
+
+pub fn read_msg<M: Message>(stream: &mut impl Read) -> Result<M, FramingError> {
+    let mut header = [0u8; HEADER_LEN];
+    stream.read_exact(&mut header[..])?;
+    let mut payload = vec![0u8; decode_header::<M>(&header)? as usize];
+    stream.read_exact(&mut payload[..])?;
+    Ok(bincode::deserialize(&payload[..])?)
+}
+
+#[cfg(feature = "async")]
+pub async fn write_msg_async<M: Message>(
+    stream: &mut (impl tokio::io::AsyncWrite + Unpin),
+    msg: &M,
+) -> Result<(), FramingError> {
+    use tokio::io::AsyncWriteExt;
+    let frame = encode_frame(msg)?;
+    stream.write_all(&frame[..]).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+#[cfg(feature = "async")]
+pub async fn read_msg_async<M: Message>(
+    stream: &mut (impl tokio::io::AsyncRead + Unpin),
+) -> Result<M, FramingError> {
+    use tokio::io::AsyncReadExt;
+    let mut header = [0u8; HEADER_LEN];
+    stream.read_exact(&mut header[..]).await?;
+    let mut payload = vec![0u8; decode_header::<M>(&header)? as usize];
+    stream.read_exact(&mut payload[..]).await?;
+    Ok(bincode::deserialize(&payload[..])?)
+}
+
+fn encode_frame<M: Message>(msg: &M) -> Result<Vec<u8>, FramingError> {
+    let payload = bincode::serialize(msg)?;
+    if payload.len() > M::MAX_SIZE as usize {
+        return Err(FramingError::MessageTooLarge {
+            msg_type: M::TYPE,
+            size: payload.len() as u32,
+            max_size: M::MAX_SIZE,
+        });
+    }
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.write_u16::<LittleEndian>(PROTOCOL_VERSION)?;
+    frame.write_u8(M::TYPE as u8)?;
+    frame.write_u32::<LittleEndian>(payload.len() as u32)?;
+    frame.write_all(&payload[..])?;
+    Ok(frame)
+}
+
+/// Returns the payload length.
+fn decode_header<M: Message>(mut header: &[u8]) -> Result<u32, FramingError> {
+    let version = header.read_u16::<LittleEndian>()?;
+    if version != PROTOCOL_VERSION {
+        return Err(FramingError::VersionMismatched {
+            expected: PROTOCOL_VERSION,
+            received: version,
+        });
+    }
+    let msg_type = header.read_u8()?;
+    if msg_type != M::TYPE as u8 {
+        return Err(FramingError::UnexpectedMessageType {
+            expected: M::TYPE,
+            received: msg_type,
+        });
+    }
+    let size = header.read_u32::<LittleEndian>()?;
+    if size > M::MAX_SIZE {
+        return Err(FramingError::MessageTooLarge {
+            msg_type: M::TYPE,
+            size,
+            max_size: M::MAX_SIZE,
+        });
+    }
+    Ok(size)
+}
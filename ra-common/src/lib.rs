@@ -1,4 +1,8 @@
+pub mod framing;
+pub mod logfmt;
 pub mod msg;
+#[cfg(feature = "sim")]
+pub mod sim;
 pub mod tcp;
 
 use sgx_crypto::cmac::{Cmac, MacTag};
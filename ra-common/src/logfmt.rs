@@ -0,0 +1,23 @@
+//! Logging of the daemons: `log!(level, msg, key = value, ...)` writes one logfmt line to stderr.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $msg:expr $(, $key:ident = $value:expr)*) => {
+        eprintln!(
+            concat!("ts={} level={} msg={:?}" $(, " ", stringify!($key), "={:?}")*),
+            $crate::logfmt::timestamp(),
+            $level,
+            $msg
+            $(, $value)*
+        )
+    };
+}
+
+/// Seconds since the Unix epoch
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_secs())
+        .unwrap_or(0)
+}
@@ -1,22 +1,31 @@
-use byteorder::{LittleEndian, WriteBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use serde::{Deserialize, Serialize};
 use serde_big_array::big_array;
 use sgx_crypto::cmac::{Cmac, MacTag};
 use sgx_crypto::error::CryptoError;
 use sgx_crypto::key_exchange::DHKEPublicKey;
 use sgx_crypto::signature::Signature;
-use std::io::Write;
+use sgx_isa::{AttributesFlags, Miscselect};
+use std::convert::TryInto;
+use std::io::{Read, Write};
 use std::mem::size_of;
 
 pub type Gid = [u8; 4];
 pub type Spid = [u8; 16];
 pub type PsSecPropDesc = [u8; 256];
-pub type Quote = [u8; 1116]; // 436 + quote.signature_len for version 2
+pub type QuoteNonce = [u8; 16];
+/// QUOTE_BODY_LEN + 4 + quote.signature_len; the signature length depends on the quote version
+/// and the size of the SigRL.
+pub type Quote = Vec<u8>;
 
-big_array! {
-    BigArray;
-    +size_of::<Quote>(),
-}
+pub const QUOTE_HEADER_LEN: usize = 48;
+pub const REPORT_BODY_LEN: usize = 384;
+/// Length of the quote up to the signature length, which is what IAS returns as
+/// `isvEnclaveQuoteBody`.
+pub const QUOTE_BODY_LEN: usize = QUOTE_HEADER_LEN + REPORT_BODY_LEN;
+const QUOTE_SIGNATURE_OFFSET: usize = QUOTE_BODY_LEN + size_of::<u32>();
+
+big_array! { BigArray; }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RaMsg0 {
@@ -87,7 +96,8 @@ pub struct RaMsg3 {
     pub mac: MacTag,
     pub g_a: DHKEPublicKey,
     pub ps_sec_prop: Option<PsSecPropDescInternal>,
-    #[serde(with = "BigArray")]
+    /// Nonce chosen by the enclave for the quote
+    pub nonce: QuoteNonce,
     pub quote: Quote,
 }
 
@@ -96,6 +106,7 @@ impl RaMsg3 {
         smk: &mut Cmac,
         g_a: DHKEPublicKey,
         ps_sec_prop: Option<PsSecPropDesc>,
+        nonce: QuoteNonce,
         quote: Quote,
     ) -> Result<Self, CryptoError> {
         let ps_sec_prop = ps_sec_prop.map(|v| PsSecPropDescInternal { inner: v });
@@ -103,6 +114,7 @@ impl RaMsg3 {
             mac: [0u8; size_of::<MacTag>()],
             g_a,
             ps_sec_prop,
+            nonce,
             quote,
         };
         let m = msg3.get_m();
@@ -122,14 +134,381 @@ impl RaMsg3 {
             m.write_all(&self.ps_sec_prop.as_ref().unwrap().inner[..])
                 .unwrap();
         }
+        m.write_all(&self.nonce[..]).unwrap();
         m.write_all(&self.quote[..]).unwrap();
         m
     }
 }
 
+/// Attestation report exactly as signed by IAS, so that the enclave can verify it itself.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IasReport {
+    /// Raw JSON body of the report
+    pub body: Vec<u8>,
+    /// Decoded `x-iasreport-signature` header
+    pub signature: Vec<u8>,
+    /// Decoded `x-iasreport-signing-certificate` header, i.e. the PEM signing certificate
+    /// followed by the PEM root CA certificate
+    pub certificate_chain: String,
+}
+
+/// MSG4 is relayed to the enclave by the untrusted client, so it is MACed with VK, which unlike
+/// SMK authenticates no other message.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RaMsg4 {
     pub is_enclave_trusted: bool,
     pub is_pse_manifest_trusted: Option<bool>,
     pub pib: Option<String>,
+    pub ias_report: Option<IasReport>,
+    pub mac: MacTag,
+}
+
+impl RaMsg4 {
+    pub fn new(
+        vk: &mut Cmac,
+        is_enclave_trusted: bool,
+        is_pse_manifest_trusted: Option<bool>,
+        pib: Option<String>,
+        ias_report: Option<IasReport>,
+    ) -> Result<Self, CryptoError> {
+        let mut msg4 = Self {
+            is_enclave_trusted,
+            is_pse_manifest_trusted,
+            pib,
+            ias_report,
+            mac: [0u8; size_of::<MacTag>()],
+        };
+        let m = msg4.get_m();
+        msg4.mac = vk.sign(&m[..])?;
+        Ok(msg4)
+    }
+
+    pub fn verify_mac(&self, vk: &mut Cmac) -> Result<(), CryptoError> {
+        let m = self.get_m();
+        vk.verify(&m[..], &self.mac)
+    }
+
+    fn get_m(&self) -> Vec<u8> {
+        let mut m = Vec::new();
+        m.write_u8(self.is_enclave_trusted as u8).unwrap();
+        // None(0), untrusted(1) or trusted(2)
+        let pse = self.is_pse_manifest_trusted.map_or(0, |t| 1 + t as u8);
+        m.write_u8(pse).unwrap();
+        // Every optional field is preceded by whether it is present
+        m.write_u8(self.pib.is_some() as u8).unwrap();
+        if let Some(pib) = self.pib.as_ref() {
+            m.write_u32::<LittleEndian>(pib.len() as u32).unwrap();
+            m.write_all(pib.as_bytes()).unwrap();
+        }
+        m.write_u8(self.ias_report.is_some() as u8).unwrap();
+        if let Some(report) = self.ias_report.as_ref() {
+            m.write_u32::<LittleEndian>(report.body.len() as u32)
+                .unwrap();
+            m.write_all(&report.body[..]).unwrap();
+            m.write_u32::<LittleEndian>(report.signature.len() as u32)
+                .unwrap();
+            m.write_all(&report.signature[..]).unwrap();
+            m.write_u32::<LittleEndian>(report.certificate_chain.len() as u32)
+                .unwrap();
+            m.write_all(report.certificate_chain.as_bytes()).unwrap();
+        }
+        m
+    }
+}
+
+// Messages between the enclave and the client, which only relays MSG2 and MSG4 to the enclave
+// and obtains the quote on its behalf.
+
+/// Enclave's public key for MSG1
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EnclavePublicKey {
+    pub g_a: DHKEPublicKey,
+}
+
+/// QE's target info, for the enclave to create a report that the QE can verify
+#[derive(Serialize, Deserialize, Debug)]
+pub struct QeTargetInfo {
+    pub target_info: Vec<u8>,
+}
+
+/// The enclave's report for the QE and the nonce that the QE report must bind the quote to
+#[derive(Serialize, Deserialize, Debug)]
+pub struct QuoteRequest {
+    pub report: Vec<u8>,
+    pub nonce: QuoteNonce,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct QuoteResponse {
+    pub quote: Quote,
+    pub qe_report: Vec<u8>,
+}
+
+/// MAC of MSG3, which only the enclave can compute
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Msg3Mac {
+    pub mac: MacTag,
+}
+
+#[derive(Debug)]
+pub enum QuoteError {
+    TooShort,
+    UnsupportedVersion(u16),
+    UnknownSignType(u16),
+    SignatureLengthMismatched,
+}
+
+/// EPID quote header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteHeader {
+    pub version: u16,
+    /// unlinkable Quote(0) or linkable Quote(1)
+    pub sign_type: u16,
+    pub epid_group_id: Gid,
+    pub qe_svn: u16,
+    pub pce_svn: u16,
+    pub xeid: u32,
+    pub basename: [u8; 32],
+}
+
+/// Raw ATTRIBUTES of a report. Bits unknown to `sgx_isa` are kept, so that policies can match
+/// them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReportAttributes {
+    pub flags: u64,
+    pub xfrm: u64,
+}
+
+impl ReportAttributes {
+    /// Flags known to `sgx_isa`
+    pub fn known_flags(&self) -> AttributesFlags {
+        AttributesFlags::from_bits_truncate(self.flags)
+    }
+}
+
+/// Body of the report of the attested enclave, as embedded in a quote.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportBody {
+    pub cpusvn: [u8; 16],
+    /// Raw MISCSELECT, including bits unknown to `sgx_isa`
+    pub miscselect: u32,
+    pub attributes: ReportAttributes,
+    pub mrenclave: [u8; 32],
+    pub mrsigner: [u8; 32],
+    pub isvprodid: u16,
+    pub isvsvn: u16,
+    pub report_data: [u8; 64],
+}
+
+/// Quote decoded from its binary form.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedQuote {
+    pub header: QuoteHeader,
+    pub report_body: ReportBody,
+    pub signature: Vec<u8>,
+}
+
+impl ParsedQuote {
+    pub fn parse(quote: &[u8]) -> Result<Self, QuoteError> {
+        if quote.len() < QUOTE_SIGNATURE_OFFSET {
+            return Err(QuoteError::TooShort);
+        }
+        let header = QuoteHeader::parse(&quote[..QUOTE_HEADER_LEN]);
+        if header.version != 1 && header.version != 2 {
+            return Err(QuoteError::UnsupportedVersion(header.version));
+        }
+        if header.sign_type > 1 {
+            return Err(QuoteError::UnknownSignType(header.sign_type));
+        }
+        let report_body = ReportBody::parse(&quote[QUOTE_HEADER_LEN..QUOTE_BODY_LEN]);
+        let signature_len = (&quote[QUOTE_BODY_LEN..QUOTE_SIGNATURE_OFFSET])
+            .read_u32::<LittleEndian>()
+            .unwrap() as usize;
+        let signature = &quote[QUOTE_SIGNATURE_OFFSET..];
+        if signature.len() != signature_len {
+            return Err(QuoteError::SignatureLengthMismatched);
+        }
+        Ok(Self {
+            header,
+            report_body,
+            signature: signature.to_vec(),
+        })
+    }
+}
+
+impl QuoteHeader {
+    /// `header` must be `QUOTE_HEADER_LEN` bytes long.
+    fn parse(mut header: &[u8]) -> Self {
+        let version = header.read_u16::<LittleEndian>().unwrap();
+        let sign_type = header.read_u16::<LittleEndian>().unwrap();
+        let mut epid_group_id = [0u8; 4];
+        header.read_exact(&mut epid_group_id).unwrap();
+        let qe_svn = header.read_u16::<LittleEndian>().unwrap();
+        let pce_svn = header.read_u16::<LittleEndian>().unwrap();
+        let xeid = header.read_u32::<LittleEndian>().unwrap();
+        let mut basename = [0u8; 32];
+        header.read_exact(&mut basename).unwrap();
+        Self {
+            version,
+            sign_type,
+            epid_group_id,
+            qe_svn,
+            pce_svn,
+            xeid,
+            basename,
+        }
+    }
+}
+
+impl ReportBody {
+    /// MISCSELECT bits known to `sgx_isa`
+    pub fn known_miscselect(&self) -> Miscselect {
+        Miscselect::from_bits_truncate(self.miscselect)
+    }
+
+    /// `body` must be `REPORT_BODY_LEN` bytes long, which `ParsedQuote::parse` checks.
+    fn parse(body: &[u8]) -> Self {
+        let u16_at = |offset: usize| (&body[offset..]).read_u16::<LittleEndian>().unwrap();
+        let u64_at = |offset: usize| (&body[offset..]).read_u64::<LittleEndian>().unwrap();
+        Self {
+            cpusvn: body[0..16].try_into().unwrap(),
+            miscselect: (&body[16..20]).read_u32::<LittleEndian>().unwrap(),
+            attributes: ReportAttributes {
+                flags: u64_at(48),
+                xfrm: u64_at(56),
+            },
+            mrenclave: body[64..96].try_into().unwrap(),
+            mrsigner: body[128..160].try_into().unwrap(),
+            isvprodid: u16_at(256),
+            isvsvn: u16_at(258),
+            report_data: body[320..384].try_into().unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIGNATURE: [u8; 5] = [0xa0, 0xa1, 0xa2, 0xa3, 0xa4];
+
+    /// Quote with a distinct value in every decoded field.
+    fn quote() -> Quote {
+        let mut quote = Vec::new();
+        quote.write_u16::<LittleEndian>(2).unwrap();
+        quote.write_u16::<LittleEndian>(1).unwrap();
+        quote.write_all(&[0x00, 0x00, 0x0b, 0x5a]).unwrap();
+        quote.write_u16::<LittleEndian>(0x0b).unwrap();
+        quote.write_u16::<LittleEndian>(0x0a).unwrap();
+        quote.write_u32::<LittleEndian>(0xdead_beef).unwrap();
+        quote.write_all(&[0x33; 32]).unwrap();
+        assert_eq!(quote.len(), QUOTE_HEADER_LEN);
+
+        let mut body = [0u8; REPORT_BODY_LEN];
+        body[0..16].copy_from_slice(&[0x01; 16]);
+        (&mut body[16..20])
+            .write_u32::<LittleEndian>(0x8000_0001)
+            .unwrap();
+        (&mut body[48..56])
+            .write_u64::<LittleEndian>(0x8000_0000_0000_0007)
+            .unwrap();
+        (&mut body[56..64]).write_u64::<LittleEndian>(0x1f).unwrap();
+        body[64..96].copy_from_slice(&[0x11; 32]);
+        body[128..160].copy_from_slice(&[0x22; 32]);
+        (&mut body[256..258]).write_u16::<LittleEndian>(7).unwrap();
+        (&mut body[258..260]).write_u16::<LittleEndian>(9).unwrap();
+        body[320..384].copy_from_slice(&[0x55; 64]);
+        quote.write_all(&body).unwrap();
+
+        quote
+            .write_u32::<LittleEndian>(SIGNATURE.len() as u32)
+            .unwrap();
+        quote.write_all(&SIGNATURE).unwrap();
+        quote
+    }
+
+    #[test]
+    fn parses_header_report_body_and_signature() {
+        let parsed = ParsedQuote::parse(&quote()).unwrap();
+
+        assert_eq!(
+            parsed.header,
+            QuoteHeader {
+                version: 2,
+                sign_type: 1,
+                epid_group_id: [0x00, 0x00, 0x0b, 0x5a],
+                qe_svn: 0x0b,
+                pce_svn: 0x0a,
+                xeid: 0xdead_beef,
+                basename: [0x33; 32],
+            }
+        );
+        assert_eq!(
+            parsed.report_body,
+            ReportBody {
+                cpusvn: [0x01; 16],
+                miscselect: 0x8000_0001,
+                attributes: ReportAttributes {
+                    flags: 0x8000_0000_0000_0007,
+                    xfrm: 0x1f,
+                },
+                mrenclave: [0x11; 32],
+                mrsigner: [0x22; 32],
+                isvprodid: 7,
+                isvsvn: 9,
+                report_data: [0x55; 64],
+            }
+        );
+        assert_eq!(parsed.signature, SIGNATURE);
+        // Unknown bits are kept, but left out of the sgx_isa types
+        assert_eq!(parsed.report_body.known_miscselect(), Miscselect::EXINFO);
+        assert_eq!(
+            parsed.report_body.attributes.known_flags(),
+            AttributesFlags::INIT | AttributesFlags::DEBUG | AttributesFlags::MODE64BIT
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_quote() {
+        let quote = quote();
+        match ParsedQuote::parse(&quote[..QUOTE_SIGNATURE_OFFSET - 1]) {
+            Err(QuoteError::TooShort) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_version() {
+        let mut quote = quote();
+        quote[0] = 3;
+        match ParsedQuote::parse(&quote) {
+            Err(QuoteError::UnsupportedVersion(3)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_sign_type() {
+        let mut quote = quote();
+        quote[2] = 2;
+        match ParsedQuote::parse(&quote) {
+            Err(QuoteError::UnknownSignType(2)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn rejects_signature_of_other_length() {
+        let mut quote = quote();
+        quote.pop();
+        match ParsedQuote::parse(&quote) {
+            Err(QuoteError::SignatureLengthMismatched) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        quote.extend_from_slice(&[0xa4, 0xa5]);
+        match ParsedQuote::parse(&quote) {
+            Err(QuoteError::SignatureLengthMismatched) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
 }
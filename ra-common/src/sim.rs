@@ -0,0 +1,132 @@
+//! Software stand-ins for the SGX hardware, the quoting enclave and AESM, used by the `sim`
+//! feature of `ra-enclave` and `ra-client` to run the attestation flow on machines without SGX.
+//! Reports and quotes produced here are structurally valid, but they are authenticated with the
+//! fixed test keys below and must never be trusted in production.
+#[cfg(target_env = "sgx")]
+compile_error!("the `sim` feature must not be enabled when building for SGX");
+
+use crate::msg::{Gid, REPORT_BODY_LEN};
+use sgx_crypto::cmac::{Cmac, MacTag};
+use sgx_crypto::digest::sha256;
+use sgx_crypto::error::CryptoError;
+use std::io::Write;
+
+/// Key used to MAC every simulated report, in place of the per-enclave report keys.
+pub const SIM_REPORT_KEY: [u8; 16] = *b"ra-sim-report-ky";
+/// Key used to "sign" simulated quotes, in place of the EPID private key.
+pub const SIM_QUOTE_KEY: [u8; 16] = *b"ra-sim-quote-key";
+pub const SIM_GID: Gid = [0x00, 0x00, 0x0b, 0x5a];
+
+pub const SIM_ENCLAVE_MRENCLAVE: [u8; 32] = [0x11; 32];
+pub const SIM_ENCLAVE_MRSIGNER: [u8; 32] = [0x22; 32];
+pub const SIM_ENCLAVE_ISVPRODID: u16 = 0;
+pub const SIM_ENCLAVE_ISVSVN: u16 = 0;
+
+pub const SIM_QE_MRENCLAVE: [u8; 32] = [0x33; 32];
+pub const SIM_QE_MRSIGNER: [u8; 32] = [0x44; 32];
+pub const SIM_QE_ISVPRODID: u16 = 1;
+pub const SIM_QE_ISVSVN: u16 = 0;
+
+pub const REPORT_LEN: usize = 432;
+pub const TARGET_INFO_LEN: usize = 512;
+const QUOTE_VERSION: u16 = 2;
+// EPID signature length with an empty SigRL
+const QUOTE_SIGNATURE_LEN: usize = 680;
+
+/// Identity of a simulated enclave.
+pub struct SimIdentity {
+    pub mrenclave: [u8; 32],
+    pub mrsigner: [u8; 32],
+    pub isvprodid: u16,
+    pub isvsvn: u16,
+}
+
+pub const SIM_ENCLAVE: SimIdentity = SimIdentity {
+    mrenclave: SIM_ENCLAVE_MRENCLAVE,
+    mrsigner: SIM_ENCLAVE_MRSIGNER,
+    isvprodid: SIM_ENCLAVE_ISVPRODID,
+    isvsvn: SIM_ENCLAVE_ISVSVN,
+};
+
+pub const SIM_QE: SimIdentity = SimIdentity {
+    mrenclave: SIM_QE_MRENCLAVE,
+    mrsigner: SIM_QE_MRSIGNER,
+    isvprodid: SIM_QE_ISVPRODID,
+    isvsvn: SIM_QE_ISVSVN,
+};
+
+/// Target info of `identity`, laid out like `sgx_isa::Targetinfo`.
+pub fn target_info(identity: &SimIdentity) -> Vec<u8> {
+    let mut target_info = vec![0u8; TARGET_INFO_LEN];
+    target_info[0..32].copy_from_slice(&identity.mrenclave);
+    target_info
+}
+
+/// Report of `identity`, laid out like `sgx_isa::Report` and MACed with `SIM_REPORT_KEY`.
+pub fn report(identity: &SimIdentity, report_data: &[u8; 64]) -> Result<Vec<u8>, CryptoError> {
+    let mut report = vec![0u8; REPORT_LEN];
+    report[64..96].copy_from_slice(&identity.mrenclave);
+    report[128..160].copy_from_slice(&identity.mrsigner);
+    report[256..258].copy_from_slice(&identity.isvprodid.to_le_bytes());
+    report[258..260].copy_from_slice(&identity.isvsvn.to_le_bytes());
+    report[320..384].copy_from_slice(&report_data[..]);
+    let mac = report_mac(&report[..])?;
+    report[416..432].copy_from_slice(&mac);
+    Ok(report)
+}
+
+/// MAC over the report body of a `REPORT_LEN`-byte report.
+pub fn report_mac(report: &[u8]) -> Result<MacTag, CryptoError> {
+    let mut cmac = Cmac::new(&SIM_REPORT_KEY)?;
+    cmac.sign(&report[..REPORT_BODY_LEN])
+}
+
+pub fn verify_report(report: &[u8]) -> Result<(), CryptoError> {
+    if report.len() != REPORT_LEN {
+        return Err(CryptoError::CmacVerificationError);
+    }
+    let mut cmac = Cmac::new(&SIM_REPORT_KEY)?;
+    let mut mac = [0u8; 16];
+    mac.copy_from_slice(&report[416..432]);
+    cmac.verify(&report[..REPORT_BODY_LEN], &mac)
+}
+
+/// Simulated quoting enclave: verify the enclave's report, then return the quote and the QE
+/// report, whose report data is SHA-256(nonce || quote) like the real QE's.
+pub fn quote(
+    report: &[u8],
+    spid: &[u8],
+    linkable: bool,
+    nonce: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), CryptoError> {
+    verify_report(report)?;
+
+    let mut quote = Vec::new();
+    quote.write_all(&QUOTE_VERSION.to_le_bytes()).unwrap();
+    quote.write_all(&(linkable as u16).to_le_bytes()).unwrap();
+    quote.write_all(&SIM_GID).unwrap();
+    quote.write_all(&SIM_QE_ISVSVN.to_le_bytes()).unwrap(); // QE SVN
+    quote.write_all(&0u16.to_le_bytes()).unwrap(); // PCE SVN
+    quote.write_all(&0u32.to_le_bytes()).unwrap(); // XEID
+    let mut basename = [0u8; 32];
+    if linkable {
+        basename[..spid.len().min(32)].copy_from_slice(&spid[..spid.len().min(32)]);
+    }
+    quote.write_all(&basename).unwrap();
+    quote.write_all(&report[..REPORT_BODY_LEN]).unwrap();
+
+    let mut signature = vec![0u8; QUOTE_SIGNATURE_LEN];
+    let mut cmac = Cmac::new(&SIM_QUOTE_KEY)?;
+    signature[..16].copy_from_slice(&cmac.sign(&quote[..])?);
+    quote
+        .write_all(&(QUOTE_SIGNATURE_LEN as u32).to_le_bytes())
+        .unwrap();
+    quote.write_all(&signature[..]).unwrap();
+
+    let mut qe_report_data = [0u8; 64];
+    let mut nonce_quote = nonce.to_vec();
+    nonce_quote.write_all(&quote[..]).unwrap();
+    qe_report_data[..32].copy_from_slice(&sha256(&nonce_quote[..])?);
+    let qe_report = self::report(&SIM_QE, &qe_report_data)?;
+    Ok((quote, qe_report))
+}
@@ -1,126 +1,562 @@
-use crate::error::EnclaveRaError;
+use crate::error::{EnclaveRaError, IasReportError};
+use crate::ias_report;
 use crate::local_attestation;
+use crate::qe_identity::QeIdentity;
 use crate::EnclaveRaResult;
 use ra_common::derive_secret_keys;
-use ra_common::msg::{Quote, RaMsg2, RaMsg3, RaMsg4};
+use ra_common::framing;
+use ra_common::msg::{
+    EnclavePublicKey, Msg3Mac, QeTargetInfo, Quote, QuoteNonce, QuoteRequest, QuoteResponse,
+    RaMsg2, RaMsg3, RaMsg4,
+};
+use sgx_crypto::certificate::X509Cert;
 use sgx_crypto::cmac::{Cmac, MacTag};
 use sgx_crypto::digest::sha256;
-use sgx_crypto::key_exchange::OneWayAuthenticatedDHKE;
+use sgx_crypto::key_exchange::{DHKEPublicKey, OneWayAuthenticatedDHKE};
 use sgx_crypto::random::Rng;
 use sgx_crypto::signature::VerificationKey;
 use sgx_isa::{Report, Targetinfo};
 use std::io::{Read, Write};
-use std::mem::size_of;
 
+/// Enclave before the protocol starts. `do_attestation` runs the whole protocol over the stream
+/// to the client; otherwise, `start` and the steps of the returned states can be driven over any
+/// transport.
 pub struct EnclaveRaContext {
-    pub key_exchange: Option<OneWayAuthenticatedDHKE>,
     pub sp_vkey: VerificationKey,
+    /// Expected identity of the quoting enclave; Intel's QE by default.
+    pub qe_identity: QeIdentity,
+    /// If set, the enclave requires the SP to forward the signed IAS report in MSG4 and verifies
+    /// it against this certificate, which must be compiled into the enclave.
+    pub ias_root_cert: Option<X509Cert>,
+    /// Quote statuses other than "OK" that the enclave accepts in a forwarded IAS report.
+    pub quote_trust_options: Vec<String>,
+    /// If set, a forwarded IAS report must contain the quote's nonce, i.e. the SP must be
+    /// configured with `random_nonce`.
+    pub require_ias_nonce: bool,
+    key_exchange: OneWayAuthenticatedDHKE,
+}
+
+/// What the enclave expects of the QE report and of a forwarded IAS report
+struct Policy {
+    qe_identity: QeIdentity,
+    ias_root_cert: Option<X509Cert>,
+    quote_trust_options: Vec<String>,
+    require_ias_nonce: bool,
+}
+
+/// Enclave that has sent its public key and is waiting for MSG2 and QE's target info.
+pub struct EnclaveAwaitingMsg2 {
+    policy: Policy,
+    sp_vkey: VerificationKey,
+    key_exchange: OneWayAuthenticatedDHKE,
+    g_a: DHKEPublicKey,
+}
+
+/// Enclave that has sent its report to the QE and is waiting for the quote.
+pub struct EnclaveAwaitingQuote {
+    policy: Policy,
+    g_a: DHKEPublicKey,
+    nonce: QuoteNonce,
+    smk: Cmac,
+    vk: Cmac,
+    sk_mk: (MacTag, MacTag),
+}
+
+/// Enclave that has sent the MAC of MSG3 and is waiting for MSG4.
+pub struct EnclaveAwaitingMsg4 {
+    policy: Policy,
+    quote: Quote,
+    nonce: QuoteNonce,
+    vk: Cmac,
+    sk_mk: (MacTag, MacTag),
 }
 
 impl EnclaveRaContext {
     pub fn init(sp_vkey_pem: &str) -> EnclaveRaResult<Self> {
-        let mut rng = Rng::new();
+        let mut rng = new_rng()?;
         let key_exchange = OneWayAuthenticatedDHKE::generate_keypair(&mut rng)?;
         Ok(Self {
             sp_vkey: VerificationKey::new(sp_vkey_pem.as_bytes())?,
-            key_exchange: Some(key_exchange),
+            qe_identity: QeIdentity::default(),
+            ias_root_cert: None,
+            quote_trust_options: Vec::new(),
+            require_ias_nonce: false,
+            key_exchange,
         })
     }
 
+    // Return (signing key, master key)
     pub fn do_attestation(
-        mut self,
+        self,
         mut client_stream: &mut (impl Read + Write),
     ) -> EnclaveRaResult<(MacTag, MacTag)> {
-        let (sk, mk) = self.process_msg_2(client_stream).unwrap();
-        let msg4: RaMsg4 = bincode::deserialize_from(&mut client_stream).unwrap();
-        if !msg4.is_enclave_trusted {
-            return Err(EnclaveRaError::EnclaveNotTrusted);
-        }
-        match msg4.is_pse_manifest_trusted {
-            Some(t) => {
-                if !t {
-                    return Err(EnclaveRaError::PseNotTrusted);
-                }
-            }
-            None => {}
-        }
-        Ok((sk, mk))
-    }
+        let (public_key, context) = self.start()?;
+        framing::write_msg(&mut client_stream, &public_key)?;
 
-    // Return (signing key, master key)
-    pub fn process_msg_2(
-        &mut self,
-        mut client_stream: &mut (impl Read + Write),
-    ) -> EnclaveRaResult<(MacTag, MacTag)> {
-        let g_a = self.key_exchange.as_ref().unwrap().get_public_key()?;
-        bincode::serialize_into(&mut client_stream, &g_a).unwrap();
-        client_stream.flush().unwrap();
+        let msg2: RaMsg2 = framing::read_msg(&mut client_stream)?;
+        let target_info: QeTargetInfo = framing::read_msg(&mut client_stream)?;
+        let (quote_request, context) = context.process_msg_2(msg2, target_info)?;
+        framing::write_msg(&mut client_stream, &quote_request)?;
 
-        let msg2: RaMsg2 = bincode::deserialize_from(&mut client_stream).unwrap();
+        let quote_response: QuoteResponse = framing::read_msg(&mut client_stream)?;
+        let (msg3_mac, context) = context.process_quote_response(quote_response)?;
+        framing::write_msg(&mut client_stream, &msg3_mac)?;
 
+        let msg4: RaMsg4 = framing::read_msg(&mut client_stream)?;
+        context.process_msg_4(msg4)
+    }
+
+    /// Returns the enclave's public key for MSG1.
+    pub fn start(self) -> EnclaveRaResult<(EnclavePublicKey, EnclaveAwaitingMsg2)> {
+        let g_a = self.key_exchange.get_public_key()?;
+        let context = EnclaveAwaitingMsg2 {
+            policy: Policy {
+                qe_identity: self.qe_identity,
+                ias_root_cert: self.ias_root_cert,
+                quote_trust_options: self.quote_trust_options,
+                require_ias_nonce: self.require_ias_nonce,
+            },
+            sp_vkey: self.sp_vkey,
+            key_exchange: self.key_exchange,
+            g_a: g_a.clone(),
+        };
+        Ok((EnclavePublicKey { g_a }, context))
+    }
+}
+
+impl EnclaveAwaitingMsg2 {
+    /// Verifies MSG2 and returns the enclave's report for the QE, whose report data is
+    /// SHA-256(g_a || g_b || vk), along with a fresh nonce for the QE report.
+    pub fn process_msg_2(
+        mut self,
+        msg2: RaMsg2,
+        target_info: QeTargetInfo,
+    ) -> EnclaveRaResult<(QuoteRequest, EnclaveAwaitingQuote)> {
         // Verify and derive KDK and then other secret keys
-        let mut rng = Rng::new();
+        let mut rng = new_rng()?;
         let kdk = self
             .key_exchange
-            .take()
-            .unwrap()
             .verify_and_derive(&msg2.g_b, &msg2.sign_gb_ga, &mut self.sp_vkey, &mut rng)
-            .unwrap();
+            .map_err(EnclaveRaError::KeyExchange)?;
         let mut kdk_cmac = Cmac::new(&kdk)?;
         let (smk, sk, mk, vk) = derive_secret_keys(&mut kdk_cmac)?;
         let mut smk = Cmac::new(&smk)?;
 
         // Verify MAC tag of MSG2
-        msg2.verify_mac(&mut smk)?;
+        msg2.verify_mac(&mut smk)
+            .map_err(|_| EnclaveRaError::IntegrityError)?;
 
         // Obtain SHA-256(g_a || g_b || vk)
         let mut verification_msg = Vec::new();
-        verification_msg.write_all(g_a.as_ref()).unwrap();
+        verification_msg.write_all(self.g_a.as_ref()).unwrap();
         verification_msg.write_all(&msg2.g_b).unwrap();
         verification_msg.write_all(&vk).unwrap();
         let verification_digest = sha256(&verification_msg[..])?;
 
-        // Obtain Quote
-        let quote = Self::get_quote(&verification_digest[..], client_stream)?;
-
-        // Send MAC for msg3 to client
-        let msg3 = RaMsg3::new(&mut smk, g_a, None, quote)?;
-        client_stream.write_all(&msg3.mac).unwrap();
-        client_stream.flush().unwrap();
-
-        Ok((sk, mk))
-    }
-
-    /// Get quote from Quote Enclave. The length of report_data must be <= 64 bytes.
-    pub fn get_quote(
-        report_data: &[u8],
-        client_stream: &mut (impl Read + Write),
-    ) -> EnclaveRaResult<Quote> {
-        if report_data.len() > 64 {
-            return Err(EnclaveRaError::ReportDataLongerThan64Bytes);
-        }
-
-        // Obtain QE's target info to build a report for local attestation.
-        // Then, send the report back to client.
-        let mut _report_data = [0u8; 64];
-        (&mut _report_data[..(report_data.len())]).copy_from_slice(report_data);
-        let mut target_info = [0u8; Targetinfo::UNPADDED_SIZE];
-        client_stream.read_exact(&mut target_info).unwrap();
-        let target_info = Targetinfo::try_copy_from(&target_info).unwrap();
-        let report = Report::for_target(&target_info, &_report_data);
-        client_stream.write_all(report.as_ref()).unwrap();
-        client_stream.flush().unwrap();
-
-        // Obtain quote and QE report from client
-        let mut quote = [0u8; size_of::<Quote>()];
-        client_stream.read_exact(&mut quote[..]).unwrap();
-        let qe_report_len = 432usize;
-        let mut qe_report = vec![0u8; qe_report_len];
-        client_stream.read_exact(&mut qe_report[..]).unwrap();
-
-        // Verify that the report is generated by QE
-        local_attestation::verify_local_attest(&qe_report[..])
-            .map_err(|e| EnclaveRaError::LocalAttestation(e))?;
-        Ok(quote)
+        // Build a report for local attestation with QE
+        let mut report_data = [0u8; 64];
+        (&mut report_data[..verification_digest.len()]).copy_from_slice(&verification_digest);
+        let target_info = Targetinfo::try_copy_from(&target_info.target_info[..])
+            .ok_or(EnclaveRaError::MalformedTargetInfo)?;
+        let report = local_attestation::report_for_target(&target_info, &report_data);
+
+        // Send a fresh nonce for the QE to include in its report
+        let mut nonce: QuoteNonce = [0u8; 16];
+        rng.fill_bytes(&mut nonce[..])?;
+
+        let quote_request = QuoteRequest {
+            report: report.as_ref().to_vec(),
+            nonce,
+        };
+        let context = EnclaveAwaitingQuote {
+            policy: self.policy,
+            g_a: self.g_a,
+            nonce,
+            smk,
+            vk: Cmac::new(&vk)?,
+            sk_mk: (sk, mk),
+        };
+        Ok((quote_request, context))
+    }
+}
+
+impl EnclaveAwaitingQuote {
+    /// Verifies the QE report and returns the MAC of MSG3, which only the enclave can compute.
+    pub fn process_quote_response(
+        mut self,
+        quote_response: QuoteResponse,
+    ) -> EnclaveRaResult<(Msg3Mac, EnclaveAwaitingMsg4)> {
+        let QuoteResponse { quote, qe_report } = quote_response;
+        verify_qe_report(
+            &qe_report[..],
+            &self.policy.qe_identity,
+            &self.nonce,
+            &quote,
+        )?;
+
+        let msg3 = RaMsg3::new(&mut self.smk, self.g_a, None, self.nonce, quote)?;
+        let context = EnclaveAwaitingMsg4 {
+            policy: self.policy,
+            quote: msg3.quote,
+            nonce: self.nonce,
+            vk: self.vk,
+            sk_mk: self.sk_mk,
+        };
+        Ok((Msg3Mac { mac: msg3.mac }, context))
+    }
+}
+
+impl EnclaveAwaitingMsg4 {
+    /// Returns (signing key, master key) if the SP trusts the enclave.
+    pub fn process_msg_4(mut self, msg4: RaMsg4) -> EnclaveRaResult<(MacTag, MacTag)> {
+        msg4.verify_mac(&mut self.vk)
+            .map_err(|_| EnclaveRaError::Msg4MacMismatched)?;
+        if !msg4.is_enclave_trusted {
+            return Err(EnclaveRaError::EnclaveNotTrusted);
+        }
+        if let Some(root_ca_cert) = self.policy.ias_root_cert.as_mut() {
+            let ias_report = msg4.ias_report.as_ref().ok_or(IasReportError::Missing)?;
+            let quote_status = ias_report::verify_ias_report(
+                root_ca_cert,
+                ias_report,
+                &self.quote,
+                &self.nonce,
+                self.policy.require_ias_nonce,
+            )?;
+            if quote_status != "OK" && !self.policy.quote_trust_options.contains(&quote_status) {
+                return Err(IasReportError::QuoteStatusNotTrusted(quote_status).into());
+            }
+        }
+        if msg4.is_pse_manifest_trusted == Some(false) {
+            return Err(EnclaveRaError::PseNotTrusted);
+        }
+        Ok(self.sk_mk)
+    }
+}
+
+/// Verify that the QE report was generated on this CPU by the expected QE, and that it binds
+/// the quote to the nonce, i.e. its report data is SHA-256(nonce || quote).
+fn verify_qe_report(
+    qe_report: &[u8],
+    qe_identity: &QeIdentity,
+    nonce: &QuoteNonce,
+    quote: &Quote,
+) -> EnclaveRaResult<()> {
+    local_attestation::verify_local_attest(qe_report)
+        .map_err(|e| EnclaveRaError::LocalAttestation(e))?;
+
+    // Can unwrap since the length is verified
+    let qe_report = Report::try_copy_from(qe_report).unwrap();
+    if qe_report.mrsigner != qe_identity.mrsigner || qe_report.isvprodid != qe_identity.isvprodid {
+        return Err(EnclaveRaError::QeIdentityMismatched);
+    }
+
+    let mut nonce_quote = nonce.to_vec();
+    nonce_quote.write_all(&quote[..]).unwrap();
+    if qe_report.reportdata[..32] != sha256(&nonce_quote[..])?[..] {
+        return Err(EnclaveRaError::QuoteNotBoundToQeReport);
+    }
+    Ok(())
+}
+
+#[cfg(target_env = "sgx")]
+fn new_rng() -> EnclaveRaResult<Rng> {
+    Ok(Rng::new())
+}
+
+// Only used by the simulation mode, which runs outside of SGX.
+#[cfg(not(target_env = "sgx"))]
+fn new_rng() -> EnclaveRaResult<Rng<'static>> {
+    Ok(Rng::new()?)
+}
+
+#[cfg(all(test, feature = "sim"))]
+mod tests {
+    use super::*;
+    use crate::error::LocalAttestationError;
+    use ra_common::framing::FramingError;
+    use ra_common::sim;
+    use sgx_crypto::signature::SigningKey;
+    use std::io::{self, Cursor};
+    use std::path::Path;
+
+    const SP_KEYS_DIR: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../ra-sp/examples/data/sp-keys"
+    );
+    const SPID: [u8; 16] = [0x5a; 16];
+
+    /// Stream that reads scripted input and collects the output.
+    struct Pipe {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn init() -> EnclaveRaContext {
+        let mut pem = std::fs::read_to_string(format!("{}/public_key.pem", SP_KEYS_DIR)).unwrap();
+        pem.push('\0');
+        EnclaveRaContext::init(&pem).unwrap()
+    }
+
+    fn start() -> (EnclavePublicKey, EnclaveAwaitingMsg2) {
+        init().start().unwrap()
+    }
+
+    /// MSG2 of the SP for `g_a`, and the SP's VK.
+    fn sp_msg2(g_a: &DHKEPublicKey) -> (RaMsg2, MacTag) {
+        let mut rng = new_rng().unwrap();
+        let mut sp_key =
+            SigningKey::new_from_file(Path::new(&format!("{}/private_key.pem", SP_KEYS_DIR)), None)
+                .unwrap();
+        let key_exchange = OneWayAuthenticatedDHKE::generate_keypair(&mut rng).unwrap();
+        let g_b = key_exchange.get_public_key().unwrap();
+        let (kdk, sign_gb_ga) = key_exchange
+            .sign_and_derive(g_a, &mut sp_key, &mut rng)
+            .unwrap();
+        let (smk, _, _, vk) = derive_secret_keys(&mut Cmac::new(&kdk).unwrap()).unwrap();
+        let msg2 = RaMsg2::new(
+            &mut Cmac::new(&smk).unwrap(),
+            g_b,
+            SPID,
+            0,
+            sign_gb_ga,
+            None,
+        );
+        (msg2.unwrap(), vk)
+    }
+
+    fn qe_target_info() -> QeTargetInfo {
+        QeTargetInfo {
+            target_info: sim::target_info(&sim::SIM_QE),
+        }
+    }
+
+    fn quote_response(quote_request: &QuoteRequest) -> QuoteResponse {
+        let (quote, qe_report) =
+            sim::quote(&quote_request.report, &SPID, false, &quote_request.nonce).unwrap();
+        QuoteResponse { quote, qe_report }
+    }
+
+    fn awaiting_quote() -> (QuoteRequest, EnclaveAwaitingQuote, MacTag) {
+        let (public_key, context) = start();
+        let (msg2, vk) = sp_msg2(&public_key.g_a);
+        let (quote_request, context) = context.process_msg_2(msg2, qe_target_info()).unwrap();
+        (quote_request, context, vk)
+    }
+
+    fn awaiting_msg4() -> (EnclaveAwaitingMsg4, MacTag) {
+        let (quote_request, context, vk) = awaiting_quote();
+        let (_, context) = context
+            .process_quote_response(quote_response(&quote_request))
+            .unwrap();
+        (context, vk)
+    }
+
+    /// Runs the enclave over a stream that answers its public key with `input`.
+    fn attest_with_input(input: Vec<u8>) -> EnclaveRaError {
+        let mut stream = Pipe {
+            input: Cursor::new(input),
+            output: Vec::new(),
+        };
+        init().do_attestation(&mut stream).unwrap_err()
+    }
+
+    fn frame(msg_type: u8, len: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = framing::PROTOCOL_VERSION.to_le_bytes().to_vec();
+        frame.push(msg_type);
+        frame.extend_from_slice(&len.to_le_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn rejects_truncated_frame() {
+        let (public_key, _) = start();
+        let (msg2, _) = sp_msg2(&public_key.g_a);
+        let mut input = Vec::new();
+        framing::write_msg(&mut input, &msg2).unwrap();
+        input.truncate(input.len() - 1);
+
+        match attest_with_input(input) {
+            EnclaveRaError::Framing(FramingError::IO(e))
+                if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_message_type() {
+        let mut input = Vec::new();
+        framing::write_msg(&mut input, &qe_target_info()).unwrap();
+
+        match attest_with_input(input) {
+            EnclaveRaError::Framing(FramingError::UnexpectedMessageType {
+                expected: framing::MsgType::Msg2,
+                received: 17,
+            }) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_length_header() {
+        match attest_with_input(frame(2, u32::MAX, &[])) {
+            EnclaveRaError::Framing(FramingError::MessageTooLarge {
+                size: 0xffff_ffff, ..
+            }) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_garbage_payload() {
+        match attest_with_input(frame(2, 4, &[0xff; 4])) {
+            EnclaveRaError::Framing(FramingError::Serialization(_)) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_msg2_signature() {
+        let (public_key, context) = start();
+        let (mut msg2, _) = sp_msg2(&public_key.g_a);
+        msg2.sign_gb_ga[0] ^= 1;
+
+        match context.process_msg_2(msg2, qe_target_info()).err() {
+            Some(EnclaveRaError::KeyExchange(_)) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_msg2_of_other_session() {
+        let (_, context) = start();
+        let (other_public_key, _) = start();
+        let (msg2, _) = sp_msg2(&other_public_key.g_a);
+
+        match context.process_msg_2(msg2, qe_target_info()).err() {
+            Some(EnclaveRaError::KeyExchange(_)) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_msg2_mac() {
+        let (public_key, context) = start();
+        let (mut msg2, _) = sp_msg2(&public_key.g_a);
+        msg2.mac[0] ^= 1;
+
+        match context.process_msg_2(msg2, qe_target_info()).err() {
+            Some(EnclaveRaError::IntegrityError) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_target_info() {
+        let (public_key, context) = start();
+        let (msg2, _) = sp_msg2(&public_key.g_a);
+        let target_info = QeTargetInfo {
+            target_info: vec![0u8; sim::TARGET_INFO_LEN - 1],
+        };
+
+        match context.process_msg_2(msg2, target_info).err() {
+            Some(EnclaveRaError::MalformedTargetInfo) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_short_qe_report() {
+        let (quote_request, context, _) = awaiting_quote();
+        let mut quote_response = quote_response(&quote_request);
+        quote_response.qe_report.pop();
+
+        match context.process_quote_response(quote_response).err() {
+            Some(EnclaveRaError::LocalAttestation(
+                LocalAttestationError::IncorrectReportLength,
+            )) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_tampered_qe_report() {
+        let (quote_request, context, _) = awaiting_quote();
+        let mut quote_response = quote_response(&quote_request);
+        // MRSIGNER
+        quote_response.qe_report[128] ^= 1;
+
+        match context.process_quote_response(quote_response).err() {
+            Some(EnclaveRaError::LocalAttestation(LocalAttestationError::IntegrityError)) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_quote_not_bound_to_qe_report() {
+        let (quote_request, context, _) = awaiting_quote();
+        let mut quote_response = quote_response(&quote_request);
+        let last = quote_response.quote.len() - 1;
+        quote_response.quote[last] ^= 1;
+
+        match context.process_quote_response(quote_response).err() {
+            Some(EnclaveRaError::QuoteNotBoundToQeReport) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_msg4_mac() {
+        let (context, mut vk) = awaiting_msg4();
+        vk[0] ^= 1;
+        let msg4 = RaMsg4::new(&mut Cmac::new(&vk).unwrap(), true, None, None, None).unwrap();
+
+        match context.process_msg_4(msg4).err() {
+            Some(EnclaveRaError::Msg4MacMismatched) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn returns_keys_if_trusted() {
+        let (context, vk) = awaiting_msg4();
+        let msg4 = RaMsg4::new(&mut Cmac::new(&vk).unwrap(), true, Some(true), None, None);
+        let sk_mk = context.sk_mk;
+
+        assert_eq!(context.process_msg_4(msg4.unwrap()).unwrap(), sk_mk);
+    }
+
+    #[test]
+    fn rejects_untrusted_enclave_or_pse() {
+        let (context, vk) = awaiting_msg4();
+        let msg4 = RaMsg4::new(&mut Cmac::new(&vk).unwrap(), false, None, None, None);
+        match context.process_msg_4(msg4.unwrap()).err() {
+            Some(EnclaveRaError::EnclaveNotTrusted) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let (context, vk) = awaiting_msg4();
+        let msg4 = RaMsg4::new(&mut Cmac::new(&vk).unwrap(), true, Some(false), None, None);
+        match context.process_msg_4(msg4.unwrap()).err() {
+            Some(EnclaveRaError::PseNotTrusted) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
     }
 }
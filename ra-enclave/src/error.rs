@@ -1,9 +1,17 @@
 #[derive(Debug)]
 pub enum EnclaveRaError {
     Crypto(sgx_crypto::error::CryptoError),
+    IO(IOError),
+    Framing(ra_common::framing::FramingError),
+    /// The SP's signature over (g_b, g_a) is invalid or g_b is malformed
+    KeyExchange(sgx_crypto::error::CryptoError),
     IntegrityError,
-    ReportDataLongerThan64Bytes,
+    MalformedTargetInfo,
     LocalAttestation(LocalAttestationError),
+    QeIdentityMismatched,
+    QuoteNotBoundToQeReport,
+    Msg4MacMismatched,
+    IasReport(IasReportError),
     EnclaveNotTrusted,
     PseNotTrusted,
 }
@@ -14,6 +22,49 @@ impl std::convert::From<sgx_crypto::error::CryptoError> for EnclaveRaError {
     }
 }
 
+#[derive(Debug)]
+pub enum IOError {
+    Bincode(std::boxed::Box<bincode::ErrorKind>),
+    StdIo(std::io::Error),
+}
+
+impl std::convert::From<std::boxed::Box<bincode::ErrorKind>> for EnclaveRaError {
+    fn from(e: std::boxed::Box<bincode::ErrorKind>) -> Self {
+        Self::IO(IOError::Bincode(e))
+    }
+}
+
+impl std::convert::From<std::io::Error> for EnclaveRaError {
+    fn from(e: std::io::Error) -> Self {
+        Self::IO(IOError::StdIo(e))
+    }
+}
+
+impl std::convert::From<ra_common::framing::FramingError> for EnclaveRaError {
+    fn from(e: ra_common::framing::FramingError) -> Self {
+        Self::Framing(e)
+    }
+}
+
+impl std::convert::From<IasReportError> for EnclaveRaError {
+    fn from(e: IasReportError) -> Self {
+        Self::IasReport(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum IasReportError {
+    Missing,
+    InvalidCertificate,
+    BadSignature,
+    MalformedBody,
+    QuoteMismatched,
+    NonceMismatched,
+    /// The SP should have sent the quote's nonce to IAS, but the report has none
+    MissingNonce,
+    QuoteStatusNotTrusted(String),
+}
+
 #[derive(Debug)]
 pub enum LocalAttestationError {
     Crypto(sgx_crypto::error::CryptoError),
@@ -0,0 +1,65 @@
+use crate::error::IasReportError;
+use ra_common::msg::{IasReport, Quote, QuoteNonce, QUOTE_BODY_LEN};
+use serde::Deserialize;
+use sgx_crypto::certificate::X509Cert;
+
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+/// Fields of the report body that the enclave checks. Others are ignored.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReportJson {
+    isv_enclave_quote_status: String,
+    isv_enclave_quote_body: String,
+    nonce: Option<String>,
+}
+
+/// Verifies that `report` is signed by a certificate issued by `root_ca_cert` and that it is the
+/// attestation report of `quote`. A nonce in the report must be `nonce`, and if `require_nonce`
+/// is set, i.e. the SP sends the nonce to IAS, the report must have one. Returns the quote
+/// status.
+pub fn verify_ias_report(
+    root_ca_cert: &mut X509Cert,
+    report: &IasReport,
+    quote: &Quote,
+    nonce: &QuoteNonce,
+    require_nonce: bool,
+) -> Result<String, IasReportError> {
+    // The signing certificate comes first in the chain. The root CA that follows is ignored in
+    // favor of the one compiled into the enclave.
+    let end = report
+        .certificate_chain
+        .find(PEM_END)
+        .ok_or(IasReportError::InvalidCertificate)?
+        + PEM_END.len();
+    let mut certificate = report.certificate_chain[..end].as_bytes().to_vec();
+    certificate.push(0);
+    let mut certificate =
+        X509Cert::new_from_pem(&certificate[..]).map_err(|_| IasReportError::InvalidCertificate)?;
+    certificate
+        .verify_this_certificate(root_ca_cert)
+        .map_err(|_| IasReportError::InvalidCertificate)?;
+    certificate
+        .verify_signature(&report.body[..], &report.signature[..])
+        .map_err(|_| IasReportError::BadSignature)?;
+
+    let body: ReportJson =
+        serde_json::from_slice(&report.body[..]).map_err(|_| IasReportError::MalformedBody)?;
+
+    // The report must be about this enclave's quote
+    let quote_body =
+        base64::decode(&body.isv_enclave_quote_body).map_err(|_| IasReportError::MalformedBody)?;
+    if quote.len() < QUOTE_BODY_LEN || quote_body[..] != quote[..QUOTE_BODY_LEN] {
+        return Err(IasReportError::QuoteMismatched);
+    }
+
+    match body.nonce {
+        Some(report_nonce) if report_nonce != hex::encode(nonce) => {
+            return Err(IasReportError::NonceMismatched)
+        }
+        None if require_nonce => return Err(IasReportError::MissingNonce),
+        _ => {}
+    }
+
+    Ok(body.isv_enclave_quote_status)
+}
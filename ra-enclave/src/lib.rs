@@ -1,8 +1,14 @@
+#[cfg(all(feature = "sim", target_env = "sgx"))]
+compile_error!("the `sim` feature must not be enabled when building for SGX");
+
 mod context;
 mod error;
+pub mod ias_report;
 pub mod local_attestation;
+mod qe_identity;
 
 pub use crate::context::*;
 pub use crate::error::*;
+pub use crate::qe_identity::*;
 
 pub type EnclaveRaResult<T> = Result<T, EnclaveRaError>;
@@ -1,20 +1,45 @@
 // Modified from https://gist.github.com/Vinc0682/10c074202c995e4f87b4edf278ec4cae
 use crate::error::LocalAttestationError;
+#[cfg(feature = "sim")]
+use ra_common::sim;
+#[cfg(not(feature = "sim"))]
 use sgx_crypto::cmac::Cmac;
-use sgx_isa::{Keyname, Keyrequest, Report, Targetinfo};
+#[cfg(not(feature = "sim"))]
+use sgx_isa::{Keyname, Keyrequest};
+use sgx_isa::{Report, Targetinfo};
 
 /// Fetches the own target info and puts it into a byte-vector.
+#[cfg(not(feature = "sim"))]
 pub fn get_own_targetinfo() -> Vec<u8> {
     let ti = Targetinfo::from(Report::for_self());
     let result: &[u8] = ti.as_ref();
     result.to_vec().clone()
 }
 
+/// Fetches the own target info and puts it into a byte-vector.
+#[cfg(feature = "sim")]
+pub fn get_own_targetinfo() -> Vec<u8> {
+    sim::target_info(&sim::SIM_ENCLAVE)
+}
+
+/// Creates a report for the given target.
+#[cfg(not(feature = "sim"))]
+pub fn report_for_target(target: &Targetinfo, data: &[u8; 64]) -> Report {
+    Report::for_target(target, data)
+}
+
+/// Creates a simulated report of `sim::SIM_ENCLAVE`, which any simulated target can verify.
+#[cfg(feature = "sim")]
+pub fn report_for_target(_target: &Targetinfo, data: &[u8; 64]) -> Report {
+    let report = sim::report(&sim::SIM_ENCLAVE, data).expect("Can't MAC simulated report");
+    Report::try_copy_from(&report[..]).unwrap()
+}
+
 /// Tries to create a report for the given target and puts it into a byte-vector.
 /// Returns None if the target is invalid, returns the report otherwise.
 pub fn locally_attest(target: &Vec<u8>, data: &[u8; 64]) -> Option<Vec<u8>> {
     let ti = Targetinfo::try_copy_from(&target[..])?;
-    let report = Report::for_target(&ti, data);
+    let report = report_for_target(&ti, data);
     let report: &[u8] = report.as_ref();
     Some(report.to_vec().clone())
 }
@@ -33,6 +58,7 @@ pub fn verify_local_attest(report: &[u8]) -> Result<(), LocalAttestationError> {
 
 /// Verifies the given report locally, a.k.a. returns true if the Report was created in an enclave
 /// on the same CPU.
+#[cfg(not(feature = "sim"))]
 pub fn verify_report(report: &Report) -> Result<(), LocalAttestationError> {
     // Derive the report key.
     let request = Keyrequest {
@@ -55,3 +81,9 @@ pub fn verify_report(report: &Report) -> Result<(), LocalAttestationError> {
     mac.verify(&mut mac_data, &report.mac)
         .map_err(|_| LocalAttestationError::IntegrityError)
 }
+
+/// Verifies the given simulated report against the simulation report key.
+#[cfg(feature = "sim")]
+pub fn verify_report(report: &Report) -> Result<(), LocalAttestationError> {
+    sim::verify_report(report.as_ref()).map_err(|_| LocalAttestationError::IntegrityError)
+}
@@ -0,0 +1,36 @@
+/// MRSIGNER of Intel's architectural enclaves, including the quoting enclave.
+pub const INTEL_QE_MRSIGNER: [u8; 32] = [
+    0xec, 0x15, 0xb1, 0x07, 0x87, 0xd2, 0xf8, 0x46, 0x67, 0xce, 0xb0, 0xb5, 0x98, 0xff, 0xc4, 0x4a,
+    0x1f, 0x1c, 0xb8, 0x0f, 0x67, 0x0a, 0xae, 0x5d, 0xf9, 0xe8, 0xfa, 0x9f, 0x63, 0x76, 0xe1, 0xf8,
+];
+/// ISVPRODID of Intel's EPID quoting enclave.
+pub const INTEL_QE_ISVPRODID: u16 = 1;
+
+/// Identity that the QE report must carry for the enclave to accept a quote.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QeIdentity {
+    pub mrsigner: [u8; 32],
+    pub isvprodid: u16,
+}
+
+#[cfg(not(feature = "sim"))]
+impl Default for QeIdentity {
+    /// Intel's quoting enclave
+    fn default() -> Self {
+        Self {
+            mrsigner: INTEL_QE_MRSIGNER,
+            isvprodid: INTEL_QE_ISVPRODID,
+        }
+    }
+}
+
+#[cfg(feature = "sim")]
+impl Default for QeIdentity {
+    /// The simulated quoting enclave
+    fn default() -> Self {
+        Self {
+            mrsigner: ra_common::sim::SIM_QE_MRSIGNER,
+            isvprodid: ra_common::sim::SIM_QE_ISVPRODID,
+        }
+    }
+}
@@ -1,9 +1,8 @@
 use byteorder::{NetworkEndian, ReadBytesExt};
-use ra_common::tcp::{tcp_accept, tcp_connect};
-use ra_sp::{SpConfig, SpRaContext};
+use ra_common::tcp::tcp_accept;
+use ra_sp::{IasClient, SpConfig, SpRaContext};
 use sgx_crypto::tls_psk::client;
 use std::io::Read;
-use std::time::Duration;
 
 fn parse_config_file(path: &str) -> SpConfig {
     serde_json::from_reader(std::fs::File::open(path).unwrap()).unwrap()
@@ -14,19 +13,13 @@ fn main() {
     let mut client_stream = tcp_accept(client_port).expect("SP: Client connection failed");
     eprintln!("SP: connected to client.");
     let config = parse_config_file("examples/data/settings.json");
-    let context = SpRaContext::init(config).unwrap();
+    let ias_client = IasClient::from_config(&config).unwrap();
+    let context = SpRaContext::init(config, ias_client).unwrap();
     let result = context.do_attestation(&mut client_stream).unwrap();
 
-    // talk to enclave directly from now on
-    let enclave_port = 1235;
-    let localhost = "localhost";
-    let timeout = Duration::from_secs(5);
-    let mut enclave_stream =
-        tcp_connect(localhost, enclave_port, timeout).expect("SP: Enclave connection failed");
-
-    // establish TLS-PSK with enclave; SP is the client
+    // establish TLS-PSK with enclave through the client's relay; SP is the client
     let mut context = client::ClientTlsPskContext::new(result.master_key);
-    let mut session = context.establish(&mut enclave_stream, None).unwrap();
+    let mut session = context.establish(&mut client_stream, None).unwrap();
     let len = session.read_u32::<NetworkEndian>().unwrap() as usize;
     let mut msg = vec![0u8; len];
     session.read_exact(&mut msg[..]).unwrap();
@@ -1,4 +1,7 @@
+use crate::identity_policy::{IdentityPolicy, MaskedValue};
+use crate::status::{PseManifestStatus, QuoteStatus};
 use serde::Deserialize;
+use std::fmt;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct SpConfig {
@@ -9,9 +12,40 @@ pub struct SpConfig {
     pub spid: String,
     pub primary_subscription_key: String,
     pub secondary_subscription_key: String,
-    pub quote_trust_options: Vec<String>,
-    pub pse_trust_options: Option<Vec<String>>,
+    pub quote_trust_options: Vec<QuoteStatus>,
+    pub pse_trust_options: Option<Vec<PseManifestStatus>>,
+    #[serde(default)]
+    pub advisory_allowlist: Vec<String>,
+    #[serde(default)]
+    pub advisory_denylist: Vec<String>,
     pub sp_private_key_pem_path: String,
     pub ias_root_cert_pem_path: String,
     pub sigstruct_path: String,
+    pub identity_policy: Option<IdentityPolicy>,
+    pub identity_policy_path: Option<String>,
+    #[serde(default)]
+    pub allow_debug: bool,
+    pub required_miscselect: Option<MaskedValue<u32>>,
+    pub required_xfrm: Option<MaskedValue<u64>>,
+    pub ias_base_url: Option<String>,
+    pub ias_api_version: Option<IasApiVersion>,
+    #[serde(default)]
+    pub forward_ias_report: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum IasApiVersion {
+    #[serde(rename = "v3")]
+    V3,
+    #[serde(rename = "v4")]
+    V4,
+}
+
+impl fmt::Display for IasApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V3 => write!(f, "v3"),
+            Self::V4 => write!(f, "v4"),
+        }
+    }
 }
@@ -1,160 +1,215 @@
 use crate::config::SpConfig;
 use crate::error::SpRaError;
-use crate::ias::IasClient;
+use crate::identity_policy::IdentityPolicy;
+use crate::status::{PseManifestStatus, QuoteStatus};
+use crate::verifier::AttestationVerifier;
 use crate::{AttestationResult, SpRaResult};
-use byteorder::{LittleEndian, ReadBytesExt};
 use ra_common::derive_secret_keys;
-use ra_common::msg::{RaMsg0, RaMsg1, RaMsg2, RaMsg3, RaMsg4, Spid};
-use sgx_crypto::certificate::X509Cert;
+use ra_common::framing;
+use ra_common::msg::{ParsedQuote, RaMsg0, RaMsg1, RaMsg2, RaMsg3, RaMsg4, Spid, QUOTE_BODY_LEN};
 use sgx_crypto::cmac::{Cmac, MacTag};
 use sgx_crypto::digest::{sha256, Sha256Digest};
 use sgx_crypto::key_exchange::{DHKEPublicKey, OneWayAuthenticatedDHKE};
 use sgx_crypto::random::Rng;
 use sgx_crypto::signature::SigningKey;
+use sgx_isa::AttributesFlags;
 use sgxs::sigstruct;
 use std::convert::TryInto;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::Path;
-
-pub struct SpRaContext<'a> {
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Long-lived SP state shared by all sessions: the config, the accepted enclave identities, the
+/// verifier (and so its HTTPS connection pool) and the SP's signing key.
+pub struct SpRaService {
     config: SpConfig,
-    sigstruct: sigstruct::Sigstruct,
-    ias_client: IasClient,
-    sp_private_key: SigningKey,
-    rng: Rng<'a>,
-    key_exchange: Option<OneWayAuthenticatedDHKE>,
-    g_a: Option<DHKEPublicKey>,
-    verification_digest: Option<Sha256Digest>,
-    smk: Option<Cmac>,
-    sk_mk: Option<(MacTag, MacTag)>,
+    spid: Spid,
+    identity_policy: IdentityPolicy,
+    verifier: Box<dyn AttestationVerifier>,
+    sp_private_key: Mutex<SigningKey>,
+}
+
+/// SP session waiting for MSG1. `do_attestation` runs the whole protocol over a stream;
+/// otherwise, `process_msg_1` and `SpAwaitingMsg3::process_msg_3` can be driven over any
+/// transport.
+pub struct SpRaContext {
+    service: Arc<SpRaService>,
+    key_exchange: OneWayAuthenticatedDHKE,
 }
 
-impl<'a> SpRaContext<'a> {
-    pub fn init(mut config: SpConfig) -> SpRaResult<Self> {
-        assert!(config.linkable, "Only Linkable Quote supported");
-        assert!(!config.random_nonce, "Random nonces not supported");
-        assert!(
-            !config.use_platform_service,
-            "Platform service not supported"
-        );
+/// SP session that has sent MSG2 and is waiting for MSG3.
+pub struct SpAwaitingMsg3 {
+    service: Arc<SpRaService>,
+    g_a: DHKEPublicKey,
+    verification_digest: Sha256Digest,
+    smk: Cmac,
+    vk: Cmac,
+    sk_mk: (MacTag, MacTag),
+}
+
+impl SpRaService {
+    pub fn init(
+        mut config: SpConfig,
+        verifier: impl AttestationVerifier + 'static,
+    ) -> SpRaResult<Self> {
+        if config.use_platform_service {
+            return Err(SpRaError::PlatformServiceNotSupported);
+        }
         if cfg!(feature = "verbose") {
             eprintln!("==================SP Config==================");
             eprintln!("{:#?}", config);
             eprintln!("=============================================");
         }
 
-        assert!(!config.spid.is_empty());
-        assert!(!config.primary_subscription_key.is_empty());
+        let spid: Spid = hex::decode(&config.spid)
+            .map_err(|_| SpRaError::InvalidSpid)?
+            .as_slice()
+            .try_into()
+            .map_err(|_| SpRaError::InvalidSpid)?;
 
         // Preparing for binary search
         config.quote_trust_options.sort();
-        config.pse_trust_options.as_mut().map(|v| v.sort());
+        if let Some(pse_trust_options) = config.pse_trust_options.as_mut() {
+            pse_trust_options.sort();
+        }
+        config.advisory_allowlist.sort();
+        config.advisory_denylist.sort();
+        validate_trust_options(&config)?;
 
         let sp_private_key =
             SigningKey::new_from_file(Path::new(&config.sp_private_key_pem_path), None)?;
 
-        let cert = X509Cert::new_from_pem_file(Path::new(&config.ias_root_cert_pem_path))?;
+        // Rules from the config come first, then those from the policy file, then the SIGSTRUCT
+        let mut identity_policy = config.identity_policy.clone().unwrap_or_default();
+        if let Some(path) = config.identity_policy_path.as_ref() {
+            let policy = IdentityPolicy::from_file(Path::new(path))?;
+            identity_policy.rules.extend(policy.rules);
+        }
+        if !config.sigstruct_path.is_empty() {
+            let mut sigstruct = File::open(Path::new(&config.sigstruct_path))?;
+            let sigstruct = sigstruct::read(&mut sigstruct)?;
+            identity_policy
+                .rules
+                .push(IdentityPolicy::rule_from_sigstruct(
+                    "sigstruct",
+                    &sigstruct,
+                )?);
+        }
+        identity_policy.require_for_all_rules(config.required_miscselect, config.required_xfrm)?;
+        identity_policy.validate()?;
+        if identity_policy.rules.is_empty() {
+            return Err(SpRaError::InvalidIdentityPolicy(
+                "no enclave identity is accepted".to_owned(),
+            ));
+        }
 
+        Ok(Self {
+            config,
+            spid,
+            identity_policy,
+            verifier: Box::new(verifier),
+            sp_private_key: Mutex::new(sp_private_key),
+        })
+    }
+
+    /// Creates the context of a new session with a fresh key pair.
+    pub fn new_session(self: &Arc<Self>) -> SpRaResult<SpRaContext> {
         let mut rng = Rng::new()?;
         let key_exchange = OneWayAuthenticatedDHKE::generate_keypair(&mut rng)?;
+        Ok(SpRaContext {
+            service: self.clone(),
+            key_exchange,
+        })
+    }
+}
 
-        let mut sigstruct = File::open(Path::new(&config.sigstruct_path))?;
-        let sigstruct = sigstruct::read(&mut sigstruct)?;
+impl SpRaContext {
+    /// Creates a single session. Use `SpRaService` to attest several clients with the same
+    /// config.
+    pub fn init(
+        config: SpConfig,
+        verifier: impl AttestationVerifier + 'static,
+    ) -> SpRaResult<Self> {
+        Arc::new(SpRaService::init(config, verifier)?).new_session()
+    }
 
-        Ok(Self {
-            config,
-            sigstruct,
-            ias_client: IasClient::new(cert),
-            sp_private_key,
-            rng,
-            key_exchange: Some(key_exchange),
-            g_a: None,
-            verification_digest: None,
-            smk: None,
-            sk_mk: None,
-        })
+    /// Blocking version of `do_attestation_async`, which runs on a new runtime, and therefore
+    /// can't be called from within a runtime.
+    pub fn do_attestation(
+        self,
+        client_stream: &mut (impl Read + Write),
+    ) -> SpRaResult<AttestationResult> {
+        let mut runtime = tokio::runtime::Runtime::new()?;
+        runtime.block_on(self.do_attestation_async(&mut BlockingStream(client_stream)))
     }
 
-    #[tokio::main]
-    pub async fn do_attestation(
-        mut self,
-        mut client_stream: &mut (impl Read + Write),
+    /// Runs the protocol on the caller's runtime, which the verifier's requests also run on.
+    pub async fn do_attestation_async(
+        self,
+        client_stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
     ) -> SpRaResult<AttestationResult> {
         // Not using MSG0 for now.
-        let _msg0: RaMsg0 = bincode::deserialize_from(&mut client_stream)?;
+        let _msg0: RaMsg0 = framing::read_msg_async(client_stream).await?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG0 received ");
         }
 
-        let msg1: RaMsg1 = bincode::deserialize_from(&mut client_stream)?;
+        let msg1: RaMsg1 = framing::read_msg_async(client_stream).await?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG1 received");
         }
 
-        let msg2 = self.process_msg_1(msg1).await?;
+        let (msg2, context) = self.process_msg_1(msg1).await?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG1 processed");
         }
 
-        bincode::serialize_into(&mut client_stream, &msg2)?;
-        client_stream.flush()?;
+        framing::write_msg_async(client_stream, &msg2).await?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG2 sent");
         }
 
-        let msg3: RaMsg3 = bincode::deserialize_from(&mut client_stream)?;
+        let msg3: RaMsg3 = framing::read_msg_async(client_stream).await?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG3 received");
         }
 
-        let (msg4, epid_pseudonym) = self.process_msg_3(msg3).await?;
+        let (msg4, attestation_result) = context.process_msg_3(msg3).await?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG4 generated");
         }
 
-        bincode::serialize_into(&mut client_stream, &msg4)?;
-        client_stream.flush()?;
+        framing::write_msg_async(client_stream, &msg4).await?;
         if cfg!(feature = "verbose") {
             eprintln!("MSG4 sent");
         }
 
-        if !msg4.is_enclave_trusted {
-            return Err(SpRaError::EnclaveNotTrusted);
-        }
-        match msg4.is_pse_manifest_trusted {
-            Some(t) => {
-                if !t {
-                    return Err(SpRaError::EnclaveNotTrusted);
-                }
-            }
-            None => {}
-        }
-
-        let (signing_key, master_key) = self.sk_mk.take().unwrap();
-
-        Ok(AttestationResult {
-            epid_pseudonym,
-            signing_key,
-            master_key,
-        })
+        attestation_result
     }
 
-    pub async fn process_msg_1(&mut self, msg1: RaMsg1) -> SpRaResult<RaMsg2> {
+    pub async fn process_msg_1(self, msg1: RaMsg1) -> SpRaResult<(RaMsg2, SpAwaitingMsg3)> {
+        let service = self.service;
+
         // Get sigRL
-        let sig_rl = self
-            .ias_client
-            .get_sig_rl(&msg1.gid, &self.config.primary_subscription_key);
+        let sig_rl = service.verifier.get_sig_rl(&msg1.gid).await?;
 
-        let key_exchange = self.key_exchange.take().unwrap();
+        let key_exchange = self.key_exchange;
         let g_b = key_exchange.get_public_key()?;
 
-        // Sign and derive KDK and other secret keys
-        let (kdk, sign_gb_ga) =
-            key_exchange.sign_and_derive(&msg1.g_a, &mut self.sp_private_key, &mut self.rng)?;
+        // Sign and derive KDK and other secret keys. The RNG is created after the await so that
+        // the future doesn't hold it.
+        let (kdk, sign_gb_ga) = {
+            let mut rng = Rng::new()?;
+            let mut sp_private_key = service.sp_private_key.lock().unwrap();
+            key_exchange.sign_and_derive(&msg1.g_a, &mut sp_private_key, &mut rng)?
+        };
         let mut kdk_cmac = Cmac::new(&kdk)?;
         let (smk, sk, mk, vk) = derive_secret_keys(&mut kdk_cmac)?;
-        let smk = Cmac::new(&smk)?;
+        let mut smk = Cmac::new(&smk)?;
 
         // Obtain SHA-256(g_a || g_b || vk)
         let mut verification_msg = Vec::new();
@@ -163,50 +218,74 @@ impl<'a> SpRaContext<'a> {
         verification_msg.write_all(&vk).unwrap();
         let verification_digest = sha256(&verification_msg[..])?;
 
-        // Set context
-        self.smk = Some(smk);
-        self.sk_mk = Some((sk, mk));
-        self.verification_digest = Some(verification_digest);
-        self.g_a = Some(msg1.g_a.clone());
-
-        let spid: Spid = hex::decode(&self.config.spid)
-            .unwrap()
-            .as_slice()
-            .try_into()
-            .unwrap();
-        let quote_type = self.config.linkable as u16;
-
-        Ok(RaMsg2::new(
-            self.smk.as_mut().unwrap(),
-            g_b,
-            spid,
-            quote_type,
-            sign_gb_ga,
-            sig_rl.await?,
-        )?)
+        let quote_type = service.config.linkable as u16;
+
+        let msg2 = RaMsg2::new(&mut smk, g_b, service.spid, quote_type, sign_gb_ga, sig_rl)?;
+        let context = SpAwaitingMsg3 {
+            service,
+            g_a: msg1.g_a,
+            verification_digest,
+            smk,
+            vk: Cmac::new(&vk)?,
+            sk_mk: (sk, mk),
+        };
+        Ok((msg2, context))
     }
+}
 
-    pub async fn process_msg_3(&mut self, msg3: RaMsg3) -> SpRaResult<(RaMsg4, Option<String>)> {
+impl SpAwaitingMsg3 {
+    /// Returns MSG4, which must be sent to the client whether or not the enclave is trusted,
+    /// and the result of the attestation.
+    pub async fn process_msg_3(
+        mut self,
+        msg3: RaMsg3,
+    ) -> SpRaResult<(RaMsg4, SpRaResult<AttestationResult>)> {
         // Integrity check
-        if &msg3.g_a[..] != &self.g_a.as_ref().unwrap()[..] {
+        if &msg3.g_a[..] != &self.g_a[..] {
             return Err(SpRaError::IntegrityError);
         }
-        if !msg3.verify_mac(self.smk.as_mut().unwrap()).is_ok() {
+        if !msg3.verify_mac(&mut self.smk).is_ok() {
             return Err(SpRaError::IntegrityError);
         }
 
-        let quote_digest: Sha256Digest = (&msg3.quote.as_ref()[368..400]).try_into().unwrap();
-        if self.verification_digest.as_ref().unwrap() != &quote_digest {
+        let quote = ParsedQuote::parse(&msg3.quote[..]).map_err(SpRaError::Quote)?;
+        if quote.header.sign_type != self.service.config.linkable as u16 {
+            return Err(SpRaError::QuoteTypeMismatched);
+        }
+        let quote_digest: Sha256Digest = quote.report_body.report_data[..32].try_into().unwrap();
+        if self.verification_digest != quote_digest {
             return Err(SpRaError::IntegrityError);
         }
 
         // Verify attestation evidence
-        // TODO: use the secondary key as well
-        let attestation_result = self
-            .ias_client
-            .verify_attestation_evidence(&msg3.quote, &self.config.primary_subscription_key)
+        let nonce = if self.service.config.random_nonce {
+            Some(&msg3.nonce)
+        } else {
+            None
+        };
+        let mut attestation_result = self
+            .service
+            .verifier
+            .verify_attestation_evidence(&msg3.quote, nonce)
             .await?;
 
+        // Whichever verifier produced it, the report must be about the quote that was submitted
+        let report_quote_body = base64::decode(&attestation_result.isv_enclave_quote_body)
+            .map_err(|_| SpRaError::ReportQuoteMismatched)?;
+        if report_quote_body[..] != msg3.quote[..QUOTE_BODY_LEN] {
+            return Err(SpRaError::ReportQuoteMismatched);
+        }
+        if let Some(nonce) = nonce {
+            if attestation_result.nonce.as_ref() != Some(&hex::encode(nonce)) {
+                return Err(SpRaError::NonceMismatched);
+            }
+        }
+
+        // IAS only returns a pseudonym for linkable quotes
+        if !self.service.config.linkable {
+            attestation_result.epid_pseudonym = None;
+        }
+
         if cfg!(feature = "verbose") {
             eprintln!("==============Attestation Result==============");
             eprintln!("{:#?}", attestation_result);
@@ -214,56 +293,277 @@ impl<'a> SpRaContext<'a> {
         }
 
         // Verify enclave identity
-        let mrenclave = &msg3.quote[112..144];
-        let mrsigner = &msg3.quote[176..208];
-        let isvprodid = (&msg3.quote[304..306]).read_u16::<LittleEndian>().unwrap();
-        let isvsvn = (&msg3.quote[306..308]).read_u16::<LittleEndian>().unwrap();
-        if mrenclave != self.sigstruct.enclavehash.as_ref()
-            || mrsigner != sha256(self.sigstruct.modulus.as_ref())?.as_ref()
-            || isvprodid != self.sigstruct.isvprodid
-            || isvsvn != self.sigstruct.isvsvn
-        {
-            return Err(SpRaError::SigstructMismatched);
+        let matched_rule = match self.service.identity_policy.evaluate(&quote.report_body) {
+            Some(rule) => rule.name.clone(),
+            None => return Err(SpRaError::IdentityNotAccepted),
+        };
+        if cfg!(feature = "verbose") {
+            eprintln!("Enclave identity matched rule {:?}", matched_rule);
         }
 
-        // Make sure the enclave is not in debug mode in production
-        let attribute_flags = &self.sigstruct.attributes.flags;
-        if cfg!(not(debug_assertions)) {
-            if (&sgx_isa::AttributesFlags::DEBUG).intersects(*attribute_flags) {
-                //return Err(SpRaError::EnclaveInDebugMode);
-                eprintln!("WARNING: Enclave is running in debug mode. Do not trust this enclave in production.")
+        // A debug enclave's memory can be read by the platform, so it must not be trusted in
+        // production
+        let report_body = &quote.report_body;
+        let config = &self.service.config;
+        if report_body
+            .attributes
+            .known_flags()
+            .contains(AttributesFlags::DEBUG)
+        {
+            if !config.allow_debug {
+                return Err(SpRaError::EnclaveInDebugMode);
             }
-        } else {
             eprintln!("WARNING: Enclave is running in debug mode. Do not trust this enclave in production.")
         }
 
         // Decide whether to trust enclave
         let quote_status = attestation_result.isv_enclave_quote_status.clone();
         let pse_manifest_status = attestation_result.pse_manifest_status.clone();
-        let is_enclave_trusted = (quote_status == "OK")
-            || self
-                .config
-                .quote_trust_options
-                .binary_search(&quote_status)
-                .is_ok();
-        let is_pse_manifest_trusted = pse_manifest_status.map(|status| {
-            (status == "OK")
-                || self
-                    .config
+        let is_enclave_trusted =
+            is_quote_trusted(config, &quote_status, &attestation_result.advisory_ids);
+        if cfg!(feature = "verbose") && !attestation_result.advisory_ids.is_empty() {
+            eprintln!(
+                "Advisories {:?}, trusted: {}",
+                attestation_result.advisory_ids, is_enclave_trusted
+            );
+        }
+        let is_pse_manifest_trusted = pse_manifest_status.as_ref().map(|status| {
+            (*status == PseManifestStatus::Ok)
+                || config
                     .pse_trust_options
                     .as_ref()
-                    .unwrap()
-                    .binary_search(&status)
-                    .is_ok()
+                    .map_or(false, |options| options.binary_search(status).is_ok())
         });
 
-        Ok((
-            RaMsg4 {
-                is_enclave_trusted,
-                is_pse_manifest_trusted,
-                pib: attestation_result.platform_info_blob,
+        let msg4 = RaMsg4::new(
+            &mut self.vk,
+            is_enclave_trusted,
+            is_pse_manifest_trusted,
+            attestation_result.platform_info_blob.clone(),
+            if config.forward_ias_report {
+                attestation_result.ias_report.clone()
+            } else {
+                None
             },
-            attestation_result.epid_pseudonym,
-        ))
+        )?;
+
+        let result = if !is_enclave_trusted || is_pse_manifest_trusted == Some(false) {
+            Err(SpRaError::EnclaveNotTrusted)
+        } else {
+            let (signing_key, master_key) = self.sk_mk;
+            Ok(AttestationResult {
+                epid_pseudonym: attestation_result.epid_pseudonym,
+                subscription_key: attestation_result.subscription_key,
+                matched_rule,
+                advisory_ids: attestation_result.advisory_ids,
+                advisory_url: attestation_result.advisory_url,
+                report_body: quote.report_body,
+                quote_status,
+                pse_manifest_status,
+                ias_request_id: attestation_result.request_id,
+                report_id: attestation_result.id,
+                timestamp: attestation_result.timestamp,
+                signing_key,
+                master_key,
+            })
+        };
+        Ok((msg4, result))
+    }
+}
+
+/// Whether a quote with `quote_status` and `advisory_ids` is trusted. A non-OK status is only
+/// accepted if every advisory is allowed, and no status is accepted with a denied advisory. The
+/// config's lists must be sorted.
+fn is_quote_trusted(
+    config: &SpConfig,
+    quote_status: &QuoteStatus,
+    advisory_ids: &[String],
+) -> bool {
+    let is_advisory_denied = advisory_ids
+        .iter()
+        .any(|id| config.advisory_denylist.binary_search(id).is_ok());
+    let are_advisories_allowed = advisory_ids
+        .iter()
+        .all(|id| config.advisory_allowlist.binary_search(id).is_ok());
+    !is_advisory_denied
+        && ((*quote_status == QuoteStatus::Ok)
+            || (config
+                .quote_trust_options
+                .binary_search(quote_status)
+                .is_ok()
+                && are_advisories_allowed))
+}
+
+/// Never called: fails to compile unless the service can be shared between threads and sessions
+/// can run on a multi-threaded runtime.
+#[allow(dead_code)]
+fn assert_thread_safety(
+    context: SpRaContext,
+    awaiting_msg3: SpAwaitingMsg3,
+    msg1: RaMsg1,
+    msg3: RaMsg3,
+) {
+    fn assert_send_sync<T: Send + Sync>() {}
+    fn assert_send<T: Send>(_: &T) {}
+
+    assert_send_sync::<SpRaService>();
+    let process_msg_1 = context.process_msg_1(msg1);
+    assert_send(&process_msg_1);
+    let process_msg_3 = awaiting_msg3.process_msg_3(msg3);
+    assert_send(&process_msg_3);
+}
+
+/// Trust options must be statuses known to the SP, and can't trust revoked or invalid quotes.
+fn validate_trust_options(config: &SpConfig) -> SpRaResult<()> {
+    for status in config.quote_trust_options.iter() {
+        if let QuoteStatus::Unknown(_) = status {
+            return Err(SpRaError::InvalidTrustOption(status.to_string()));
+        }
+        if status.is_revoked_or_invalid() {
+            return Err(SpRaError::InvalidTrustOption(status.to_string()));
+        }
+    }
+    for status in config.pse_trust_options.iter().flatten() {
+        if let PseManifestStatus::Unknown(_) = status {
+            return Err(SpRaError::InvalidTrustOption(status.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Adapts a blocking stream for `do_attestation_async`. Every poll blocks, so this must only be
+/// used on a runtime of its own.
+struct BlockingStream<'s, S>(&'s mut S);
+
+impl<'s, S: Read> AsyncRead for BlockingStream<'s, S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(self.0.read(buf))
+    }
+}
+
+impl<'s, S: Write> AsyncWrite for BlockingStream<'s, S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(self.0.write(buf))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(self.0.flush())
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SA_1: &str = "INTEL-SA-00001";
+    const SA_2: &str = "INTEL-SA-00002";
+
+    /// Config trusting GROUP_OUT_OF_DATE, with sorted advisory lists.
+    fn config(allowlist: &[&str], denylist: &[&str]) -> SpConfig {
+        let mut config: SpConfig = serde_json::from_value(json!({
+            "debug": false,
+            "linkable": false,
+            "random_nonce": true,
+            "use_platform_service": false,
+            "spid": "",
+            "primary_subscription_key": "",
+            "secondary_subscription_key": "",
+            "quote_trust_options": ["GROUP_OUT_OF_DATE"],
+            "advisory_allowlist": allowlist,
+            "advisory_denylist": denylist,
+            "sp_private_key_pem_path": "",
+            "ias_root_cert_pem_path": "",
+            "sigstruct_path": ""
+        }))
+        .unwrap();
+        config.advisory_allowlist.sort();
+        config.advisory_denylist.sort();
+        config
+    }
+
+    fn advisories(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|&id| id.to_owned()).collect()
+    }
+
+    #[test]
+    fn empty_allowlist_only_trusts_options_without_advisories() {
+        let config = config(&[], &[]);
+        let out_of_date = QuoteStatus::GroupOutOfDate;
+        assert!(is_quote_trusted(&config, &out_of_date, &[]));
+        assert!(!is_quote_trusted(
+            &config,
+            &out_of_date,
+            &advisories(&[SA_1])
+        ));
+        assert!(is_quote_trusted(
+            &config,
+            &QuoteStatus::Ok,
+            &advisories(&[SA_1])
+        ));
+        assert!(!is_quote_trusted(
+            &config,
+            &QuoteStatus::ConfigurationNeeded,
+            &[]
+        ));
+    }
+
+    #[test]
+    fn non_ok_status_needs_every_advisory_allowed() {
+        let config = config(&[SA_1], &[]);
+        let out_of_date = QuoteStatus::GroupOutOfDate;
+        assert!(is_quote_trusted(
+            &config,
+            &out_of_date,
+            &advisories(&[SA_1])
+        ));
+        assert!(!is_quote_trusted(
+            &config,
+            &out_of_date,
+            &advisories(&[SA_1, SA_2])
+        ));
+    }
+
+    #[test]
+    fn denied_advisory_rejects_otherwise_trusted_status() {
+        let config = config(&[], &[SA_2]);
+        assert!(!is_quote_trusted(
+            &config,
+            &QuoteStatus::Ok,
+            &advisories(&[SA_2])
+        ));
+        assert!(!is_quote_trusted(
+            &config,
+            &QuoteStatus::GroupOutOfDate,
+            &advisories(&[SA_2])
+        ));
+    }
+
+    #[test]
+    fn denylist_wins_over_allowlist() {
+        let config = config(&[SA_1, SA_2], &[SA_2]);
+        let out_of_date = QuoteStatus::GroupOutOfDate;
+        assert!(is_quote_trusted(
+            &config,
+            &out_of_date,
+            &advisories(&[SA_1])
+        ));
+        assert!(!is_quote_trusted(
+            &config,
+            &out_of_date,
+            &advisories(&[SA_1, SA_2])
+        ));
     }
 }
@@ -4,8 +4,22 @@ pub enum SpRaError {
     IO(std::io::Error),
     IAS(IasError),
     Serialization(std::boxed::Box<bincode::ErrorKind>),
+    Framing(ra_common::framing::FramingError),
+    Quote(ra_common::msg::QuoteError),
     IntegrityError,
-    SigstructMismatched,
+    NonceMismatched,
+    /// The attestation report is about another quote than the one in MSG3
+    ReportQuoteMismatched,
+    QuoteTypeMismatched,
+    InvalidIdentityPolicy(String),
+    InvalidTrustOption(String),
+    /// `primary_subscription_key` is empty
+    MissingSubscriptionKey,
+    /// `spid` is not 16 hex-encoded bytes
+    InvalidSpid,
+    /// `use_platform_service` is set, but PSE is not supported
+    PlatformServiceNotSupported,
+    IdentityNotAccepted,
     EnclaveInDebugMode,
     EnclaveNotTrusted,
 }
@@ -34,6 +48,12 @@ impl std::convert::From<std::boxed::Box<bincode::ErrorKind>> for SpRaError {
     }
 }
 
+impl std::convert::From<ra_common::framing::FramingError> for SpRaError {
+    fn from(e: ra_common::framing::FramingError) -> Self {
+        Self::Framing(e)
+    }
+}
+
 impl std::fmt::Display for SpRaError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         write!(f, "{:?}", self)
@@ -45,6 +65,15 @@ impl std::error::Error for SpRaError {}
 #[derive(Debug)]
 pub enum AttestationError {
     Connection(http::StatusCode),
+    MissingHeader(&'static str),
+    /// Header that is not ASCII, or not percent-encoded UTF-8
+    InvalidHeader(&'static str),
+    /// Fewer than two PEM certificates in the signing certificate chain
+    MissingCertificate,
+    MalformedCertificate,
+    InvalidSignatureEncoding,
+    BodyNotUtf8,
+    MalformedBody(serde_json::Error),
     MismatchedIASRootCertificate,
     InvalidIASCertificate,
     BadSignature,
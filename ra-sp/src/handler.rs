@@ -0,0 +1,88 @@
+use crate::AttestationResult;
+use async_trait::async_trait;
+use serde_json::json;
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+
+/// Receives the keys of every successful attestation session, e.g. to hand them to the service
+/// that talks to the enclave.
+#[async_trait]
+pub trait PostAttestationHandler: Send + Sync {
+    /// `peer` is the address of the client that was attested.
+    async fn handle(&self, peer: &str, result: &AttestationResult) -> std::io::Result<()>;
+}
+
+/// JSON object of the session, followed by a newline, as written by the handlers below:
+/// `{"peer": ..., "epid_pseudonym": ..., "matched_rule": ..., "mrenclave": hex, "mrsigner": hex,
+/// "isvprodid": ..., "isvsvn": ..., "quote_status": ..., "advisory_ids": [...],
+/// "ias_request_id": ..., "report_id": ..., "timestamp": ..., "signing_key": hex,
+/// "master_key": hex}`.
+pub fn session_json(peer: &str, result: &AttestationResult) -> Vec<u8> {
+    let mut session = json!({
+        "peer": peer,
+        "epid_pseudonym": result.epid_pseudonym,
+        "matched_rule": result.matched_rule,
+        "mrenclave": hex::encode(&result.report_body.mrenclave),
+        "mrsigner": hex::encode(&result.report_body.mrsigner),
+        "isvprodid": result.report_body.isvprodid,
+        "isvsvn": result.report_body.isvsvn,
+        "quote_status": result.quote_status.as_str(),
+        "advisory_ids": result.advisory_ids,
+        "ias_request_id": result.ias_request_id,
+        "report_id": result.report_id,
+        "timestamp": result.timestamp,
+        "signing_key": hex::encode(&result.signing_key),
+        "master_key": hex::encode(&result.master_key),
+    })
+    .to_string()
+    .into_bytes();
+    session.push(b'\n');
+    session
+}
+
+/// Runs `program` for every session, writing `session_json` to its stdin. Keys are not passed in
+/// arguments or environment variables, which other users of the host may be able to read. The
+/// program is killed if the handler is dropped before it exits, e.g. when it times out.
+pub struct ExecHandler {
+    pub program: PathBuf,
+}
+
+#[async_trait]
+impl PostAttestationHandler for ExecHandler {
+    async fn handle(&self, peer: &str, result: &AttestationResult) -> std::io::Result<()> {
+        let mut child = tokio::process::Command::new(&self.program)
+            .stdin(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        {
+            let mut stdin = child.stdin.take().unwrap();
+            stdin.write_all(&session_json(peer, result)[..]).await?;
+        }
+        let status = child.await?;
+        if !status.success() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("{} exited with {}", self.program.display(), status),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Connects to the Unix socket at `path` for every session and writes `session_json` to it.
+#[cfg(unix)]
+pub struct UnixSocketHandler {
+    pub path: PathBuf,
+}
+
+#[cfg(unix)]
+#[async_trait]
+impl PostAttestationHandler for UnixSocketHandler {
+    async fn handle(&self, peer: &str, result: &AttestationResult) -> std::io::Result<()> {
+        let mut stream = tokio::net::UnixStream::connect(&self.path).await?;
+        stream.write_all(&session_json(peer, result)[..]).await?;
+        stream.shutdown(std::net::Shutdown::Write)?;
+        Ok(())
+    }
+}
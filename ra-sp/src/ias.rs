@@ -1,70 +1,179 @@
 use crate::attestation_response::AttestationResponse;
-use crate::error::{AttestationError, IasError};
+use crate::config::{IasApiVersion, SpConfig};
+use crate::error::{AttestationError, IasError, SpRaError};
+use crate::verifier::AttestationVerifier;
+use crate::SpRaResult;
+use async_trait::async_trait;
 use hyper::body::HttpBody as _;
-use hyper::{client::HttpConnector, Body, Client, Request};
+use hyper::{client::HttpConnector, Body, Client, Request, Response, StatusCode};
 use hyper_tls::HttpsConnector;
-use ra_common::msg::{Gid, Quote};
+use ra_common::msg::{Gid, Quote, QuoteNonce};
 use sgx_crypto::certificate::X509Cert;
-use std::io::Write;
+use std::path::Path;
 
-const BASE_URI: &str = "https://api.trustedservices.intel.com/sgx/dev";
-const SIG_RL_PATH: &str = "/attestation/v3/sigrl/";
-const REPORT_PATH: &str = "/attestation/v3/report";
+pub const DEFAULT_BASE_URI: &str = "https://api.trustedservices.intel.com/sgx/dev";
+pub const DEFAULT_API_VERSION: IasApiVersion = IasApiVersion::V3;
+
+/// Subscription key with which an IAS request succeeded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubscriptionKey {
+    Primary,
+    Secondary,
+}
 
 pub struct IasClient {
     https_client: Client<HttpsConnector<HttpConnector>>,
     root_ca_cert: X509Cert,
+    primary_subscription_key: String,
+    secondary_subscription_key: Option<String>,
+    base_uri: String,
+    api_version: IasApiVersion,
 }
 
 impl IasClient {
-    pub fn new(root_ca_cert: X509Cert) -> Self {
+    /// `base_uri` selects the IAS environment, e.g. `DEFAULT_BASE_URI` for development,
+    /// `https://api.trustedservices.intel.com/sgx` for production, or a local mirror.
+    /// Requests are retried with `secondary_subscription_key`, if any, when the primary key is
+    /// rejected or throttled, or when the connection fails.
+    pub fn new(
+        root_ca_cert: X509Cert,
+        primary_subscription_key: String,
+        secondary_subscription_key: Option<String>,
+        base_uri: String,
+        api_version: IasApiVersion,
+    ) -> Self {
         Self {
             https_client: Client::builder().build::<_, hyper::Body>(HttpsConnector::new()),
             root_ca_cert,
+            primary_subscription_key,
+            secondary_subscription_key,
+            base_uri: base_uri.trim_end_matches('/').to_owned(),
+            api_version,
+        }
+    }
+
+    /// Build a client from the IAS root certificate, subscription keys, endpoint and API
+    /// version in `config`.
+    pub fn from_config(config: &SpConfig) -> SpRaResult<Self> {
+        if config.primary_subscription_key.is_empty() {
+            return Err(SpRaError::MissingSubscriptionKey);
         }
+        let cert = X509Cert::new_from_pem_file(Path::new(&config.ias_root_cert_pem_path))?;
+        let base_uri = config
+            .ias_base_url
+            .clone()
+            .unwrap_or_else(|| DEFAULT_BASE_URI.to_owned());
+        let api_version = config.ias_api_version.unwrap_or(DEFAULT_API_VERSION);
+        let secondary_subscription_key =
+            Some(config.secondary_subscription_key.clone()).filter(|key| !key.is_empty());
+        Ok(Self::new(
+            cert,
+            config.primary_subscription_key.clone(),
+            secondary_subscription_key,
+            base_uri,
+            api_version,
+        ))
+    }
+
+    fn sig_rl_uri(&self, gid: &Gid) -> String {
+        format!(
+            "{}/attestation/{}/sigrl/{:02x}{:02x}{:02x}{:02x}",
+            self.base_uri, self.api_version, gid[0], gid[1], gid[2], gid[3]
+        )
+    }
+
+    fn report_uri(&self) -> String {
+        format!("{}/attestation/{}/report", self.base_uri, self.api_version)
     }
 
-    pub async fn get_sig_rl(
+    /// Send the request built by `build` with the primary subscription key. If the request fails
+    /// in a way that may be caused by the key, send it again with the secondary key.
+    async fn request(
         &self,
-        gid: &Gid,
-        subscription_key: &str,
-    ) -> Result<Option<Vec<u8>>, IasError> {
-        let uri = format!(
-            "{}{}{:02x}{:02x}{:02x}{:02x}",
-            BASE_URI, SIG_RL_PATH, gid[0], gid[1], gid[2], gid[3]
-        );
-        let req = Request::get(uri)
-            .header("Ocp-Apim-Subscription-Key", subscription_key)
-            .body(Body::empty())
-            .unwrap();
-        let mut resp = self.https_client.request(req).await?;
+        build: impl Fn(&str) -> Request<Body>,
+    ) -> Result<(Response<Body>, SubscriptionKey), IasError> {
+        let resp = self
+            .https_client
+            .request(build(&self.primary_subscription_key))
+            .await;
+        let secondary_subscription_key = match &self.secondary_subscription_key {
+            Some(key) => key,
+            None => return Ok((resp?, SubscriptionKey::Primary)),
+        };
+        match resp {
+            Ok(resp) if !Self::should_fail_over(resp.status()) => {
+                Ok((resp, SubscriptionKey::Primary))
+            }
+            _ => {
+                if cfg!(feature = "verbose") {
+                    eprintln!(
+                        "IAS request failed with the primary key; retrying with the secondary key"
+                    );
+                }
+                let resp = self
+                    .https_client
+                    .request(build(secondary_subscription_key))
+                    .await?;
+                Ok((resp, SubscriptionKey::Secondary))
+            }
+        }
+    }
+
+    /// Unauthorized, forbidden and throttled requests are likely caused by the subscription key.
+    fn should_fail_over(status: StatusCode) -> bool {
+        status == StatusCode::UNAUTHORIZED
+            || status == StatusCode::FORBIDDEN
+            || status == StatusCode::TOO_MANY_REQUESTS
+    }
+}
+
+#[async_trait]
+impl AttestationVerifier for IasClient {
+    async fn get_sig_rl(&self, gid: &Gid) -> Result<Option<Vec<u8>>, IasError> {
+        let uri = self.sig_rl_uri(gid);
+        let (mut resp, _) = self
+            .request(|subscription_key| {
+                Request::get(&uri)
+                    .header("Ocp-Apim-Subscription-Key", subscription_key)
+                    .body(Body::empty())
+                    .unwrap()
+            })
+            .await?;
         if resp.status().as_u16() != 200 {
             return Err(IasError::SigRLError(resp.status()));
         }
-        if resp.headers().get("content-length").unwrap() == "0" {
-            return Ok(None);
-        }
+        // An empty SigRL may come without content-length, so it is recognized by its body
         let mut sig_rl = Vec::new();
         while let Some(chunk) = resp.body_mut().data().await {
-            sig_rl.write_all(&chunk.unwrap()).unwrap();
+            sig_rl.extend_from_slice(&chunk?);
         }
-        Ok(Some(sig_rl))
+        Ok(Some(sig_rl).filter(|sig_rl| !sig_rl.is_empty()))
     }
 
-    pub async fn verify_attestation_evidence(
+    async fn verify_attestation_evidence(
         &self,
         quote: &Quote,
-        subscription_key: &str,
+        nonce: Option<&QuoteNonce>,
     ) -> Result<AttestationResponse, IasError> {
-        let uri = format!("{}{}", BASE_URI, REPORT_PATH);
         let quote_base64 = base64::encode(&quote[..]);
-        let body = format!("{{\"isvEnclaveQuote\":\"{}\"}}", quote_base64);
-        let req = Request::post(uri)
-            .header("Content-type", "application/json")
-            .header("Ocp-Apim-Subscription-Key", subscription_key)
-            .body(Body::from(body))
-            .unwrap();
-        let mut resp = self.https_client.request(req).await?;
+        let body = match nonce {
+            Some(nonce) => format!(
+                "{{\"isvEnclaveQuote\":\"{}\",\"nonce\":\"{}\"}}",
+                quote_base64,
+                hex::encode(nonce)
+            ),
+            None => format!("{{\"isvEnclaveQuote\":\"{}\"}}", quote_base64),
+        };
+        let uri = self.report_uri();
+        let (mut resp, subscription_key) = self
+            .request(|subscription_key| {
+                Request::post(&uri)
+                    .header("Content-type", "application/json")
+                    .header("Ocp-Apim-Subscription-Key", subscription_key)
+                    .body(Body::from(body.clone()))
+                    .unwrap()
+            })
+            .await?;
         if resp.status().as_u16() != 200 {
             return Err(IasError::Attestation(AttestationError::Connection(
                 resp.status(),
@@ -72,10 +181,13 @@ impl IasClient {
         }
         let mut body = Vec::new();
         while let Some(chunk) = resp.body_mut().data().await {
-            body.write_all(&chunk.unwrap()).unwrap();
+            body.extend_from_slice(&chunk?);
         }
 
-        AttestationResponse::from_response(&self.root_ca_cert, resp.headers(), body)
-            .map_err(|e| IasError::Attestation(e))
+        let mut attestation_response =
+            AttestationResponse::from_response(&self.root_ca_cert, resp.headers(), body)
+                .map_err(|e| IasError::Attestation(e))?;
+        attestation_response.subscription_key = Some(subscription_key);
+        Ok(attestation_response)
     }
 }
@@ -0,0 +1,408 @@
+use crate::error::SpRaError;
+use crate::SpRaResult;
+use ra_common::msg::ReportBody;
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use sgx_crypto::digest::sha256;
+use sgx_isa::AttributesFlags;
+use sgxs::sigstruct::Sigstruct;
+use std::convert::TryInto;
+use std::fs::File;
+use std::ops::{BitAnd, BitOr};
+use std::path::Path;
+
+pub type Measurement = [u8; 32];
+
+/// Enclave identities accepted by the SP. An enclave is accepted if its quote matches any of the
+/// rules, which are tried in order.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct IdentityPolicy {
+    pub rules: Vec<IdentityRule>,
+}
+
+/// Either pins an exact `mrenclave`, or accepts any enclave signed by `mrsigner` with
+/// `isvprodid` and an ISVSVN of at least `min_isvsvn`. Measurements are hex-encoded in JSON.
+#[derive(Deserialize, Debug, Clone)]
+pub struct IdentityRule {
+    /// Reported in `AttestationResult::matched_rule`.
+    pub name: String,
+    #[serde(default, deserialize_with = "deserialize_measurement")]
+    pub mrenclave: Option<Measurement>,
+    #[serde(default, deserialize_with = "deserialize_measurement")]
+    pub mrsigner: Option<Measurement>,
+    pub isvprodid: Option<u16>,
+    pub min_isvsvn: Option<u16>,
+    pub miscselect: Option<MaskedValue<u32>>,
+    pub attributes_flags: Option<MaskedValue<u64>>,
+    pub attributes_xfrm: Option<MaskedValue<u64>>,
+}
+
+/// Matches values whose bits set in `mask` are equal to those of `value`.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct MaskedValue<T> {
+    pub value: T,
+    pub mask: T,
+}
+
+impl<T> MaskedValue<T>
+where
+    T: Copy + PartialEq + BitAnd<Output = T> + BitOr<Output = T>,
+{
+    pub fn matches(&self, bits: T) -> bool {
+        bits & self.mask == self.value & self.mask
+    }
+
+    /// Matches the values that both `self` and `other` match, or `None` if there are none.
+    pub fn intersect(&self, other: &Self) -> Option<Self> {
+        let common = self.mask & other.mask;
+        if self.value & common != other.value & common {
+            return None;
+        }
+        Some(Self {
+            value: (self.value & self.mask) | (other.value & other.mask),
+            mask: self.mask | other.mask,
+        })
+    }
+}
+
+impl IdentityPolicy {
+    pub fn from_file(path: &Path) -> SpRaResult<Self> {
+        let policy: Self = serde_json::from_reader(File::open(path)?)
+            .map_err(|e| SpRaError::InvalidIdentityPolicy(e.to_string()))?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Rule accepting the enclave signed with `sigstruct`, with the MISCSELECT and attributes it
+    /// requires. Unlike the other fields, ISVSVN is a minimum, so the same enclave re-signed with a
+    /// higher ISVSVN is accepted too. The DEBUG flag is left to `SpConfig::allow_debug`, and INIT is
+    /// ignored since it is set in every report but never in a SIGSTRUCT.
+    pub fn rule_from_sigstruct(name: &str, sigstruct: &Sigstruct) -> SpRaResult<IdentityRule> {
+        Ok(IdentityRule {
+            name: name.to_owned(),
+            mrenclave: Some(sigstruct.enclavehash),
+            mrsigner: Some(sha256(sigstruct.modulus.as_ref())?),
+            isvprodid: Some(sigstruct.isvprodid),
+            min_isvsvn: Some(sigstruct.isvsvn),
+            miscselect: Some(MaskedValue {
+                value: sigstruct.miscselect.bits(),
+                mask: sigstruct.miscmask,
+            }),
+            attributes_flags: Some(MaskedValue {
+                value: sigstruct.attributes.flags.bits(),
+                mask: sigstruct.attributemask[0]
+                    & !(AttributesFlags::DEBUG | AttributesFlags::INIT).bits(),
+            }),
+            attributes_xfrm: Some(MaskedValue {
+                value: sigstruct.attributes.xfrm,
+                mask: sigstruct.attributemask[1],
+            }),
+        })
+    }
+
+    /// Adds MISCSELECT and XFRM requirements to every rule, on top of the rule's own. Fails if a
+    /// rule can't satisfy both.
+    pub fn require_for_all_rules(
+        &mut self,
+        miscselect: Option<MaskedValue<u32>>,
+        xfrm: Option<MaskedValue<u64>>,
+    ) -> SpRaResult<()> {
+        let contradiction = |rule: &IdentityRule, field: &str| {
+            SpRaError::InvalidIdentityPolicy(format!(
+                "rule {:?} contradicts required_{}",
+                rule.name, field
+            ))
+        };
+        for rule in self.rules.iter_mut() {
+            if let Some(required) = miscselect {
+                rule.miscselect = match rule.miscselect {
+                    Some(m) => Some(
+                        m.intersect(&required)
+                            .ok_or_else(|| contradiction(rule, "miscselect"))?,
+                    ),
+                    None => Some(required),
+                };
+            }
+            if let Some(required) = xfrm {
+                rule.attributes_xfrm = match rule.attributes_xfrm {
+                    Some(m) => Some(
+                        m.intersect(&required)
+                            .ok_or_else(|| contradiction(rule, "xfrm"))?,
+                    ),
+                    None => Some(required),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Every rule must pin either MRENCLAVE, or MRSIGNER and ISVPRODID.
+    pub fn validate(&self) -> SpRaResult<()> {
+        for rule in self.rules.iter() {
+            if rule.mrenclave.is_none() && (rule.mrsigner.is_none() || rule.isvprodid.is_none()) {
+                return Err(SpRaError::InvalidIdentityPolicy(format!(
+                    "rule {:?} must set mrenclave, or mrsigner and isvprodid",
+                    rule.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the first rule matching the enclave that produced `report_body`.
+    pub fn evaluate(&self, report_body: &ReportBody) -> Option<&IdentityRule> {
+        self.rules.iter().find(|rule| rule.matches(report_body))
+    }
+}
+
+impl IdentityRule {
+    pub fn matches(&self, report_body: &ReportBody) -> bool {
+        self.mrenclave
+            .map_or(true, |mrenclave| mrenclave == report_body.mrenclave)
+            && self
+                .mrsigner
+                .map_or(true, |mrsigner| mrsigner == report_body.mrsigner)
+            && self
+                .isvprodid
+                .map_or(true, |isvprodid| isvprodid == report_body.isvprodid)
+            && self
+                .min_isvsvn
+                .map_or(true, |min_isvsvn| report_body.isvsvn >= min_isvsvn)
+            && self
+                .miscselect
+                .map_or(true, |m| m.matches(report_body.miscselect))
+            && self
+                .attributes_flags
+                .map_or(true, |m| m.matches(report_body.attributes.flags))
+            && self
+                .attributes_xfrm
+                .map_or(true, |m| m.matches(report_body.attributes.xfrm))
+    }
+}
+
+fn deserialize_measurement<'de, D>(deserializer: D) -> Result<Option<Measurement>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let hex: Option<String> = Option::deserialize(deserializer)?;
+    hex.map(|hex| {
+        let bytes = hex::decode(&hex).map_err(D::Error::custom)?;
+        bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| D::Error::custom("measurement must be 32 bytes"))
+    })
+    .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ra_common::msg::ReportAttributes;
+    use sgx_isa::{Attributes, Miscselect};
+
+    fn report_body() -> ReportBody {
+        ReportBody {
+            cpusvn: [0; 16],
+            miscselect: 0,
+            attributes: ReportAttributes {
+                flags: (AttributesFlags::INIT | AttributesFlags::MODE64BIT).bits(),
+                xfrm: 0x3,
+            },
+            mrenclave: [0x11; 32],
+            mrsigner: [0x22; 32],
+            isvprodid: 1,
+            isvsvn: 2,
+            report_data: [0; 64],
+        }
+    }
+
+    fn sigstruct() -> Sigstruct {
+        let mut sigstruct = Sigstruct::try_copy_from(&[0; Sigstruct::UNPADDED_SIZE]).unwrap();
+        sigstruct.enclavehash = [0x11; 32];
+        sigstruct.isvprodid = 1;
+        sigstruct.isvsvn = 2;
+        sigstruct.miscselect = Miscselect::empty();
+        sigstruct.miscmask = !0;
+        sigstruct.attributes = Attributes {
+            flags: AttributesFlags::MODE64BIT,
+            xfrm: 0x3,
+        };
+        sigstruct.attributemask = [!0, !0];
+        sigstruct
+    }
+
+    fn rule(name: &str) -> IdentityRule {
+        IdentityRule {
+            name: name.to_owned(),
+            mrenclave: Some([0x11; 32]),
+            mrsigner: None,
+            isvprodid: None,
+            min_isvsvn: None,
+            miscselect: None,
+            attributes_flags: None,
+            attributes_xfrm: None,
+        }
+    }
+
+    #[test]
+    fn mrenclave_rule_pins_exact_enclave() {
+        let rule = rule("pinned");
+        let mut report_body = report_body();
+        assert!(rule.matches(&report_body));
+
+        report_body.mrenclave[0] ^= 1;
+        assert!(!rule.matches(&report_body));
+    }
+
+    #[test]
+    fn mrsigner_rule_accepts_isvsvn_from_minimum() {
+        let rule = IdentityRule {
+            mrenclave: None,
+            mrsigner: Some([0x22; 32]),
+            isvprodid: Some(1),
+            min_isvsvn: Some(2),
+            ..rule("signer")
+        };
+        let mut report_body = report_body();
+        for &(isvsvn, accepted) in [(1, false), (2, true), (3, true)].iter() {
+            report_body.isvsvn = isvsvn;
+            assert_eq!(rule.matches(&report_body), accepted, "ISVSVN {}", isvsvn);
+        }
+
+        report_body.isvprodid = 2;
+        assert!(!rule.matches(&report_body));
+        report_body.isvprodid = 1;
+        report_body.mrsigner[0] ^= 1;
+        assert!(!rule.matches(&report_body));
+    }
+
+    #[test]
+    fn rule_masks_only_compare_masked_bits() {
+        let rule = IdentityRule {
+            miscselect: Some(MaskedValue {
+                value: 0x1,
+                mask: 0x1,
+            }),
+            attributes_flags: Some(MaskedValue {
+                value: 0,
+                mask: AttributesFlags::DEBUG.bits(),
+            }),
+            attributes_xfrm: Some(MaskedValue {
+                value: 0x3,
+                mask: 0x3,
+            }),
+            ..rule("masks")
+        };
+        let mut report_body = report_body();
+        report_body.miscselect = 0x1 | 0x8;
+        report_body.attributes.xfrm = 0x3 | 0x4;
+        assert!(rule.matches(&report_body));
+
+        report_body.miscselect = 0x8;
+        assert!(!rule.matches(&report_body));
+        report_body.miscselect = 0x1;
+        report_body.attributes.flags |= AttributesFlags::DEBUG.bits();
+        assert!(!rule.matches(&report_body));
+        report_body.attributes.flags &= !AttributesFlags::DEBUG.bits();
+        report_body.attributes.xfrm = 0x1;
+        assert!(!rule.matches(&report_body));
+    }
+
+    #[test]
+    fn sigstruct_rule_pins_sigstruct_identity() {
+        let rule = IdentityPolicy::rule_from_sigstruct("sigstruct", &sigstruct()).unwrap();
+        assert_eq!(rule.name, "sigstruct");
+        assert_eq!(rule.mrenclave, Some([0x11; 32]));
+        assert_eq!(rule.mrsigner, Some(sha256(&[0; 384]).unwrap()));
+        assert_eq!(rule.isvprodid, Some(1));
+        assert_eq!(rule.min_isvsvn, Some(2));
+
+        let mut report_body = report_body();
+        report_body.mrsigner = rule.mrsigner.unwrap();
+        report_body.isvsvn = 3;
+        assert!(rule.matches(&report_body));
+        report_body.isvsvn = 1;
+        assert!(!rule.matches(&report_body));
+        report_body.isvsvn = 2;
+        report_body.miscselect = 0x1;
+        assert!(!rule.matches(&report_body));
+        report_body.miscselect = 0;
+        report_body.attributes.xfrm = 0x7;
+        assert!(!rule.matches(&report_body));
+    }
+
+    #[test]
+    fn sigstruct_rule_matches_report_with_init_set() {
+        let rule = IdentityPolicy::rule_from_sigstruct("sigstruct", &sigstruct()).unwrap();
+        let mut report_body = report_body();
+        report_body.mrsigner = rule.mrsigner.unwrap();
+        assert!(rule.matches(&report_body));
+
+        report_body.attributes.flags |= AttributesFlags::PROVISIONKEY.bits();
+        assert!(!rule.matches(&report_body));
+    }
+
+    #[test]
+    fn intersect_combines_compatible_masks() {
+        let a = MaskedValue::<u64> {
+            value: 0b0001,
+            mask: 0b0011,
+        };
+        let b = MaskedValue::<u64> {
+            value: 0b0101,
+            mask: 0b0101,
+        };
+        let both = a.intersect(&b).unwrap();
+        assert_eq!((both.value, both.mask), (0b0101, 0b0111));
+        assert!(both.matches(0b1101));
+        assert!(!both.matches(0b0111));
+
+        let contradicting = MaskedValue::<u64> {
+            value: 0b0010,
+            mask: 0b0010,
+        };
+        assert!(a.intersect(&contradicting).is_none());
+    }
+
+    #[test]
+    fn required_values_are_merged_into_every_rule() {
+        let mut policy = IdentityPolicy {
+            rules: vec![rule("a"), rule("b")],
+        };
+        policy.rules[1].miscselect = Some(MaskedValue { value: 0, mask: 1 });
+        let miscselect = MaskedValue { value: 0, mask: 2 };
+        let xfrm = MaskedValue { value: 3, mask: 3 };
+        policy
+            .require_for_all_rules(Some(miscselect), Some(xfrm))
+            .unwrap();
+
+        let report_body = report_body();
+        for rule in policy.rules.iter() {
+            assert_eq!(rule.attributes_xfrm.unwrap().mask, 3);
+            assert!(rule.matches(&report_body));
+        }
+        assert_eq!(policy.rules[1].miscselect.unwrap().mask, 3);
+    }
+
+    #[test]
+    fn required_value_contradicting_a_rule_is_rejected() {
+        let mut policy = IdentityPolicy {
+            rules: vec![rule("a"), rule("b")],
+        };
+        policy.rules[1].attributes_xfrm = Some(MaskedValue {
+            value: 0x7,
+            mask: 0x7,
+        });
+        let xfrm = MaskedValue {
+            value: 0x3,
+            mask: 0x7,
+        };
+        match policy.require_for_all_rules(None, Some(xfrm)) {
+            Err(SpRaError::InvalidIdentityPolicy(message)) => {
+                assert_eq!(message, "rule \"b\" contradicts required_xfrm")
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
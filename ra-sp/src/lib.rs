@@ -2,18 +2,47 @@ mod attestation_response;
 mod config;
 mod context;
 mod error;
+mod handler;
 mod ias;
+mod identity_policy;
+#[cfg(feature = "mock-ias")]
+pub mod mock_ias;
+mod status;
+mod verifier;
 
+pub use crate::attestation_response::*;
 pub use crate::config::*;
 pub use crate::context::*;
 pub use crate::error::*;
+pub use crate::handler::*;
+pub use crate::ias::*;
+pub use crate::identity_policy::*;
+pub use crate::status::*;
+pub use crate::verifier::*;
 
 pub type SpRaResult<T> = Result<T, crate::error::SpRaError>;
 
+use ra_common::msg::ReportBody;
 use sgx_crypto::cmac::MacTag;
 
 pub struct AttestationResult {
+    /// Only present for linkable quotes
     pub epid_pseudonym: Option<String>,
+    pub subscription_key: Option<SubscriptionKey>,
+    /// Name of the identity policy rule the enclave matched
+    pub matched_rule: String,
+    /// INTEL-SA IDs of the security advisories that apply to the platform
+    pub advisory_ids: Vec<String>,
+    pub advisory_url: Option<String>,
+    /// Identity of the enclave, from its quote
+    pub report_body: ReportBody,
+    pub quote_status: QuoteStatus,
+    pub pse_manifest_status: Option<PseManifestStatus>,
+    /// `request-id` of the IAS response
+    pub ias_request_id: String,
+    /// ID and timestamp of the IAS report
+    pub report_id: String,
+    pub timestamp: String,
     pub signing_key: MacTag,
     pub master_key: MacTag,
 }
@@ -0,0 +1,237 @@
+//! SP attestation daemon: attests every client that connects, concurrently, and hands the keys
+//! of each successful session to a post-attestation handler. Logs are written to stderr as
+//! logfmt lines.
+use ra_common::log;
+#[cfg(unix)]
+use ra_sp::UnixSocketHandler;
+use ra_sp::{ExecHandler, IasClient, PostAttestationHandler, SpConfig, SpRaContext, SpRaService};
+use std::fs::File;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+const USAGE: &str = "Usage: ra-sp --config <settings.json> [--listen <address>] \
+                     [--session-timeout <seconds>] [--handler-timeout <seconds>] \
+                     [--exec <program> | --unix-socket <path>]";
+const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:1234";
+const DEFAULT_SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+const DEFAULT_HANDLER_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Args {
+    config: PathBuf,
+    listen: String,
+    session_timeout: Duration,
+    handler_timeout: Duration,
+    handler: Option<Arc<dyn PostAttestationHandler>>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut config = None;
+    let mut listen = DEFAULT_LISTEN_ADDRESS.to_owned();
+    let mut session_timeout = DEFAULT_SESSION_TIMEOUT;
+    let mut handler_timeout = DEFAULT_HANDLER_TIMEOUT;
+    let mut handler: Option<Arc<dyn PostAttestationHandler>> = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--config" => config = Some(PathBuf::from(value()?)),
+            "--listen" => listen = value()?,
+            "--session-timeout" => {
+                let seconds = value()?
+                    .parse()
+                    .map_err(|_| "Invalid session timeout".to_owned())?;
+                session_timeout = Duration::from_secs(seconds);
+            }
+            "--handler-timeout" => {
+                let seconds = value()?
+                    .parse()
+                    .map_err(|_| "Invalid handler timeout".to_owned())?;
+                handler_timeout = Duration::from_secs(seconds);
+            }
+            "--exec" => {
+                handler = Some(Arc::new(ExecHandler {
+                    program: PathBuf::from(value()?),
+                }))
+            }
+            #[cfg(unix)]
+            "--unix-socket" => {
+                handler = Some(Arc::new(UnixSocketHandler {
+                    path: PathBuf::from(value()?),
+                }))
+            }
+            _ => return Err(format!("Unknown argument {}", arg)),
+        }
+    }
+
+    Ok(Args {
+        config: config.ok_or("Missing --config".to_owned())?,
+        listen,
+        session_timeout,
+        handler_timeout,
+        handler,
+    })
+}
+
+#[tokio::main]
+async fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = run(args).await {
+        log!("error", "exiting", error = e.to_string());
+        std::process::exit(1);
+    }
+}
+
+async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    let config: SpConfig = serde_json::from_reader(File::open(&args.config)?)?;
+    let ias_client = IasClient::from_config(&config)?;
+    let service = Arc::new(SpRaService::init(config, ias_client)?);
+
+    let mut listener = TcpListener::bind(&args.listen).await?;
+    log!("info", "listening", address = args.listen);
+    if args.handler.is_none() {
+        log!(
+            "warn",
+            "no post-attestation handler, session keys will be discarded"
+        );
+    }
+
+    // Every session holds a sender, so that the receiver returns once all of them are done.
+    let (sessions, mut sessions_done) = mpsc::channel::<()>(1);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    log!("warn", "accept failed", error = e.to_string());
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        let session = match service.new_session() {
+            Ok(session) => session,
+            Err(e) => {
+                log!(
+                    "error",
+                    "session setup failed",
+                    peer = peer.to_string(),
+                    error = e.to_string()
+                );
+                continue;
+            }
+        };
+        let handler = args.handler.clone();
+        let session_timeout = args.session_timeout;
+        let handler_timeout = args.handler_timeout;
+        let sessions = sessions.clone();
+        let session = async move {
+            run_session(
+                session,
+                stream,
+                peer,
+                session_timeout,
+                handler_timeout,
+                handler,
+            )
+            .await;
+            drop(sessions);
+        };
+        assert_send(&session);
+        tokio::spawn(session);
+    }
+
+    log!("info", "shutting down, waiting for sessions in progress");
+    drop(sessions);
+    sessions_done.recv().await;
+    log!("info", "shut down");
+    Ok(())
+}
+
+async fn run_session(
+    session: SpRaContext,
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    session_timeout: Duration,
+    handler_timeout: Duration,
+    handler: Option<Arc<dyn PostAttestationHandler>>,
+) {
+    let peer = peer.to_string();
+    let start = Instant::now();
+    log!("info", "session started", peer = peer);
+
+    let attestation = session.do_attestation_async(&mut stream);
+    let result = match tokio::time::timeout(session_timeout, attestation).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => {
+            log!(
+                "warn",
+                "attestation failed",
+                peer = peer,
+                error = e.to_string()
+            );
+            return;
+        }
+        Err(_) => {
+            log!("warn", "attestation timed out", peer = peer);
+            return;
+        }
+    };
+    log!(
+        "info",
+        "attestation succeeded",
+        peer = peer,
+        epid_pseudonym = result.epid_pseudonym,
+        matched_rule = result.matched_rule,
+        quote_status = result.quote_status.as_str(),
+        ias_request_id = result.ias_request_id,
+        duration_ms = start.elapsed().as_millis()
+    );
+
+    // A stuck handler would otherwise hold the session, and so shutdown, forever
+    if let Some(handler) = handler {
+        match tokio::time::timeout(handler_timeout, handler.handle(&peer, &result)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => log!(
+                "error",
+                "post-attestation handler failed",
+                peer = peer,
+                error = e.to_string()
+            ),
+            Err(_) => log!("error", "post-attestation handler timed out", peer = peer),
+        }
+    }
+}
+
+/// Sessions run on the runtime's worker threads. Checked here, rather than by `tokio::spawn`, to
+/// point at the session if it stops being `Send`.
+fn assert_send<T: Send>(_: &T) {}
+
+/// Completes on Ctrl-C, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Can't listen to SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
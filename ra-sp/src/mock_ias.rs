@@ -0,0 +1,380 @@
+//! In-process mock of the Intel Attestation Service, so that `SpRaContext::do_attestation` can be
+//! exercised without Intel's services. Reports are signed by a freshly generated test CA whose
+//! root certificate is handed to `IasClient`, and the responses can be scripted with
+//! `MockIasBehavior`.
+use crate::config::IasApiVersion;
+use crate::ias::IasClient;
+use crate::SpRaResult;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use ra_common::msg::QUOTE_BODY_LEN;
+use serde_json::{json, Value};
+use sgx_crypto::certificate::X509Cert;
+use sgx_crypto::mbedtls::hash::Type as MdType;
+use sgx_crypto::mbedtls::pk::Pk;
+use sgx_crypto::mbedtls::x509::certificate::Builder;
+use sgx_crypto::mbedtls::x509::Time;
+use sgx_crypto::random::Rng;
+use sgx_crypto::signature::SigningKey;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use tokio::sync::oneshot;
+
+const RSA_KEY_BITS: u32 = 2048;
+const RSA_EXPONENT: u32 = 0x10001;
+const ROOT_CA_SUBJECT: &str = "CN=Mock SGX Attestation Report Signing CA\0";
+const SIGNING_SUBJECT: &str = "CN=Mock SGX Attestation Report Signing\0";
+pub const PRIMARY_SUBSCRIPTION_KEY: &str = "mock-primary-subscription-key";
+pub const SECONDARY_SUBSCRIPTION_KEY: &str = "mock-secondary-subscription-key";
+
+/// Scripted responses of `MockIas`.
+#[derive(Debug, Clone)]
+pub struct MockIasBehavior {
+    /// Status code of `/sigrl/{gid}` responses.
+    pub sig_rl_status: StatusCode,
+    pub sig_rl: Option<Vec<u8>>,
+    /// Status code of `/report` responses. No report is returned if this is not 200.
+    pub report_status: StatusCode,
+    pub isv_enclave_quote_status: String,
+    pub advisory_url: Option<String>,
+    pub advisory_ids: Vec<String>,
+    pub timestamp: String,
+    pub revocation_reason: Option<u32>,
+    pub pse_manifest_status: Option<String>,
+    pub pse_manifest_hash: Option<String>,
+    pub platform_info_blob: Option<String>,
+    pub epid_pseudonym: Option<String>,
+    /// Corrupt `x-iasreport-signature` so that report verification fails.
+    pub bad_signature: bool,
+    /// Status code of every request made with a subscription key, e.g. 401 for a revoked key,
+    /// instead of the responses above.
+    pub subscription_key_status: HashMap<String, StatusCode>,
+}
+
+impl Default for MockIasBehavior {
+    fn default() -> Self {
+        Self {
+            sig_rl_status: StatusCode::OK,
+            sig_rl: None,
+            report_status: StatusCode::OK,
+            isv_enclave_quote_status: "OK".to_owned(),
+            advisory_url: None,
+            advisory_ids: Vec::new(),
+            timestamp: "2020-01-01T00:00:00.000000".to_owned(),
+            revocation_reason: None,
+            pse_manifest_status: None,
+            pse_manifest_hash: None,
+            platform_info_blob: None,
+            epid_pseudonym: None,
+            bad_signature: false,
+            subscription_key_status: HashMap::new(),
+        }
+    }
+}
+
+struct MockIasState {
+    behavior: Mutex<MockIasBehavior>,
+    signing_key: Mutex<SigningKey>,
+    signing_certificate_chain: String,
+    report_count: AtomicU64,
+    subscription_keys: Mutex<Vec<String>>,
+}
+
+pub struct MockIas {
+    addr: SocketAddr,
+    root_ca_cert_pem: String,
+    state: Arc<MockIasState>,
+    shutdown: Option<oneshot::Sender<()>>,
+    server_thread: Option<JoinHandle<()>>,
+}
+
+impl MockIas {
+    /// Generate a test CA and start serving on a random local port.
+    pub fn start(behavior: MockIasBehavior) -> SpRaResult<Self> {
+        let mut rng = Rng::new()?;
+        let (root_ca_cert_pem, signing_cert_pem, signing_key) = generate_test_ca(&mut rng)?;
+        let state = Arc::new(MockIasState {
+            behavior: Mutex::new(behavior),
+            signing_key: Mutex::new(signing_key),
+            signing_certificate_chain: format!("{}{}", signing_cert_pem, root_ca_cert_pem),
+            report_count: AtomicU64::new(0),
+            subscription_keys: Mutex::new(Vec::new()),
+        });
+
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        let addr = listener.local_addr()?;
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server_state = state.clone();
+        let server_thread = thread::spawn(move || {
+            let mut rt = tokio::runtime::Runtime::new().expect("Mock IAS: cannot start runtime");
+            rt.block_on(async move {
+                let make_service = make_service_fn(move |_| {
+                    let state = server_state.clone();
+                    async move {
+                        Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req)))
+                    }
+                });
+                let server = Server::from_tcp(listener)
+                    .expect("Mock IAS: cannot listen")
+                    .serve(make_service)
+                    .with_graceful_shutdown(async {
+                        shutdown_rx.await.ok();
+                    });
+                if let Err(e) = server.await {
+                    eprintln!("Mock IAS: server error: {}", e);
+                }
+            })
+        });
+
+        Ok(Self {
+            addr,
+            root_ca_cert_pem,
+            state,
+            shutdown: Some(shutdown),
+            server_thread: Some(server_thread),
+        })
+    }
+
+    /// Base URI to be passed to `IasClient`.
+    pub fn base_uri(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Root certificate of the test CA, e.g. to be compiled into a test enclave.
+    pub fn root_ca_cert_pem(&self) -> &str {
+        &self.root_ca_cert_pem
+    }
+
+    pub fn root_ca_cert(&self) -> SpRaResult<X509Cert> {
+        let mut pem = self.root_ca_cert_pem.clone();
+        pem.push('\0');
+        Ok(X509Cert::new_from_pem(pem.as_bytes())?)
+    }
+
+    /// `IasClient` talking to this server and trusting its test CA, with
+    /// `PRIMARY_SUBSCRIPTION_KEY` only.
+    pub fn ias_client(&self, api_version: IasApiVersion) -> SpRaResult<IasClient> {
+        Ok(IasClient::new(
+            self.root_ca_cert()?,
+            PRIMARY_SUBSCRIPTION_KEY.to_owned(),
+            None,
+            self.base_uri(),
+            api_version,
+        ))
+    }
+
+    /// Like `ias_client`, with `SECONDARY_SUBSCRIPTION_KEY` to fail over to.
+    pub fn ias_client_with_secondary_key(
+        &self,
+        api_version: IasApiVersion,
+    ) -> SpRaResult<IasClient> {
+        Ok(IasClient::new(
+            self.root_ca_cert()?,
+            PRIMARY_SUBSCRIPTION_KEY.to_owned(),
+            Some(SECONDARY_SUBSCRIPTION_KEY.to_owned()),
+            self.base_uri(),
+            api_version,
+        ))
+    }
+
+    /// Subscription keys of the requests received so far, in order.
+    pub fn subscription_keys(&self) -> Vec<String> {
+        self.state.subscription_keys.lock().unwrap().clone()
+    }
+
+    pub fn set_behavior(&self, behavior: MockIasBehavior) {
+        *self.state.behavior.lock().unwrap() = behavior;
+    }
+}
+
+impl Drop for MockIas {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(server_thread) = self.server_thread.take() {
+            let _ = server_thread.join();
+        }
+    }
+}
+
+/// Return (root CA certificate PEM, report signing certificate PEM, report signing key).
+pub(crate) fn generate_test_ca(rng: &mut Rng) -> sgx_crypto::Result<(String, String, SigningKey)> {
+    let mut root_key = Pk::generate_rsa(&mut rng.inner, RSA_KEY_BITS, RSA_EXPONENT)?;
+    let root_key_der = root_key.write_private_der_vec()?;
+    let mut root_issuer_key = Pk::from_private_key(&root_key_der[..], None)?;
+    let mut signing_key = Pk::generate_rsa(&mut rng.inner, RSA_KEY_BITS, RSA_EXPONENT)?;
+    let signing_key_der = signing_key.write_private_der_vec()?;
+    let not_before = Time::new(2020, 1, 1, 0, 0, 0).unwrap();
+    let not_after = Time::new(2049, 12, 31, 23, 59, 59).unwrap();
+
+    let root_ca_cert_pem = Builder::new()
+        .subject_key(&mut root_key)
+        .subject_with_nul(ROOT_CA_SUBJECT)?
+        .issuer_key(&mut root_issuer_key)
+        .issuer_with_nul(ROOT_CA_SUBJECT)?
+        .basic_constraints(true, None)?
+        .validity(not_before, not_after)?
+        .serial(&[1])?
+        .signature_hash(MdType::Sha256)
+        .write_pem_string(&mut rng.inner)?;
+
+    let signing_cert_pem = Builder::new()
+        .subject_key(&mut signing_key)
+        .subject_with_nul(SIGNING_SUBJECT)?
+        .issuer_key(&mut root_issuer_key)
+        .issuer_with_nul(ROOT_CA_SUBJECT)?
+        .basic_constraints(false, None)?
+        .validity(not_before, not_after)?
+        .serial(&[2])?
+        .signature_hash(MdType::Sha256)
+        .write_pem_string(&mut rng.inner)?;
+
+    let signing_key = SigningKey::new(&signing_key_der[..], None)?;
+    Ok((root_ca_cert_pem, signing_cert_pem, signing_key))
+}
+
+async fn handle(
+    state: Arc<MockIasState>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let subscription_key = req
+        .headers()
+        .get("ocp-apim-subscription-key")
+        .and_then(|key| key.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    state
+        .subscription_keys
+        .lock()
+        .unwrap()
+        .push(subscription_key.clone());
+    let key_status = state
+        .behavior
+        .lock()
+        .unwrap()
+        .subscription_key_status
+        .get(&subscription_key)
+        .cloned();
+    if let Some(status) = key_status {
+        return Ok(empty_response(status));
+    }
+
+    let path = req.uri().path().to_owned();
+    let resp = match (req.method(), path.rsplit('/').nth(1)) {
+        (&Method::GET, Some("sigrl")) => sig_rl_response(&state),
+        (&Method::POST, _) if path.ends_with("/report") => {
+            let version = if path.contains("/v4/") { 4 } else { 3 };
+            match hyper::body::to_bytes(req.into_body()).await {
+                Ok(body) => report_response(&state, version, &body[..]),
+                Err(_) => empty_response(StatusCode::BAD_REQUEST),
+            }
+        }
+        _ => empty_response(StatusCode::NOT_FOUND),
+    };
+    Ok(resp)
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-length", "0")
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn sig_rl_response(state: &MockIasState) -> Response<Body> {
+    let behavior = state.behavior.lock().unwrap();
+    match (behavior.sig_rl_status, &behavior.sig_rl) {
+        (StatusCode::OK, Some(sig_rl)) => Response::builder()
+            .header("content-length", sig_rl.len())
+            .body(Body::from(sig_rl.clone()))
+            .unwrap(),
+        (status, _) => empty_response(status),
+    }
+}
+
+fn report_response(state: &MockIasState, version: u16, request_body: &[u8]) -> Response<Body> {
+    let behavior = state.behavior.lock().unwrap().clone();
+    if behavior.report_status != StatusCode::OK {
+        return empty_response(behavior.report_status);
+    }
+
+    let request: Value = match serde_json::from_slice(request_body) {
+        Ok(request) => request,
+        Err(_) => return empty_response(StatusCode::BAD_REQUEST),
+    };
+    let quote = match request["isvEnclaveQuote"].as_str().map(base64::decode) {
+        Some(Ok(quote)) if quote.len() >= QUOTE_BODY_LEN => quote,
+        _ => return empty_response(StatusCode::BAD_REQUEST),
+    };
+
+    let report_count = state.report_count.fetch_add(1, Ordering::SeqCst);
+    let mut report = json!({
+        "id": format!("{}", report_count),
+        "timestamp": behavior.timestamp,
+        "version": version,
+        "isvEnclaveQuoteStatus": behavior.isv_enclave_quote_status,
+        "isvEnclaveQuoteBody": base64::encode(&quote[..QUOTE_BODY_LEN]),
+    });
+    let optional_fields = [
+        ("pseManifestStatus", &behavior.pse_manifest_status),
+        ("pseManifestHash", &behavior.pse_manifest_hash),
+        ("platformInfoBlob", &behavior.platform_info_blob),
+        ("epidPseudonym", &behavior.epid_pseudonym),
+    ];
+    if let Some(nonce) = request["nonce"].as_str() {
+        report["nonce"] = json!(nonce);
+    }
+    if let Some(revocation_reason) = behavior.revocation_reason {
+        report["revocationReason"] = json!(revocation_reason);
+    }
+    for (name, value) in optional_fields.iter() {
+        if let Some(value) = value {
+            report[*name] = json!(value);
+        }
+    }
+    if version >= 4 {
+        if let Some(advisory_url) = &behavior.advisory_url {
+            report["advisoryURL"] = json!(advisory_url);
+        }
+        if !behavior.advisory_ids.is_empty() {
+            report["advisoryIDs"] = json!(behavior.advisory_ids);
+        }
+    }
+    let report = report.to_string().into_bytes();
+
+    let mut signature = Rng::new()
+        .and_then(|mut rng| {
+            state
+                .signing_key
+                .lock()
+                .unwrap()
+                .sign(&report[..], &mut rng)
+        })
+        .expect("Mock IAS: cannot sign report");
+    if behavior.bad_signature {
+        signature[0] ^= 0xff;
+    }
+
+    let mut resp = Response::builder()
+        .header("content-type", "application/json")
+        .header("request-id", format!("{:032x}", report_count))
+        .header("x-iasreport-signature", base64::encode(&signature[..]))
+        .header(
+            "x-iasreport-signing-certificate",
+            utf8_percent_encode(&state.signing_certificate_chain, NON_ALPHANUMERIC).to_string(),
+        );
+    if let Some(advisory_url) = &behavior.advisory_url {
+        resp = resp.header("advisory-url", advisory_url.as_str());
+    }
+    if !behavior.advisory_ids.is_empty() {
+        resp = resp.header("advisory-ids", behavior.advisory_ids.join(","));
+    }
+    resp.body(Body::from(report)).unwrap()
+}
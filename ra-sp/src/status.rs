@@ -0,0 +1,128 @@
+use serde::Deserialize;
+use std::fmt;
+
+/// `isvEnclaveQuoteStatus` of IAS API versions 3 and 4.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(from = "String")]
+pub enum QuoteStatus {
+    Ok,
+    SignatureInvalid,
+    GroupRevoked,
+    SignatureRevoked,
+    KeyRevoked,
+    SigrlVersionMismatch,
+    GroupOutOfDate,
+    ConfigurationNeeded,
+    SwHardeningNeeded,
+    ConfigurationAndSwHardeningNeeded,
+    /// Status not known to this version of the SP
+    Unknown(String),
+}
+
+impl QuoteStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Ok => "OK",
+            Self::SignatureInvalid => "SIGNATURE_INVALID",
+            Self::GroupRevoked => "GROUP_REVOKED",
+            Self::SignatureRevoked => "SIGNATURE_REVOKED",
+            Self::KeyRevoked => "KEY_REVOKED",
+            Self::SigrlVersionMismatch => "SIGRL_VERSION_MISMATCH",
+            Self::GroupOutOfDate => "GROUP_OUT_OF_DATE",
+            Self::ConfigurationNeeded => "CONFIGURATION_NEEDED",
+            Self::SwHardeningNeeded => "SW_HARDENING_NEEDED",
+            Self::ConfigurationAndSwHardeningNeeded => "CONFIGURATION_AND_SW_HARDENING_NEEDED",
+            Self::Unknown(status) => status,
+        }
+    }
+
+    /// Statuses that can never be trusted, whatever `SpConfig::quote_trust_options` says.
+    pub fn is_revoked_or_invalid(&self) -> bool {
+        matches!(
+            self,
+            Self::SignatureInvalid | Self::GroupRevoked | Self::SignatureRevoked | Self::KeyRevoked
+        )
+    }
+}
+
+impl From<&str> for QuoteStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "OK" => Self::Ok,
+            "SIGNATURE_INVALID" => Self::SignatureInvalid,
+            "GROUP_REVOKED" => Self::GroupRevoked,
+            "SIGNATURE_REVOKED" => Self::SignatureRevoked,
+            "KEY_REVOKED" => Self::KeyRevoked,
+            "SIGRL_VERSION_MISMATCH" => Self::SigrlVersionMismatch,
+            "GROUP_OUT_OF_DATE" => Self::GroupOutOfDate,
+            "CONFIGURATION_NEEDED" => Self::ConfigurationNeeded,
+            "SW_HARDENING_NEEDED" => Self::SwHardeningNeeded,
+            "CONFIGURATION_AND_SW_HARDENING_NEEDED" => Self::ConfigurationAndSwHardeningNeeded,
+            status => Self::Unknown(status.to_owned()),
+        }
+    }
+}
+
+impl From<String> for QuoteStatus {
+    fn from(status: String) -> Self {
+        Self::from(status.as_str())
+    }
+}
+
+impl fmt::Display for QuoteStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// `pseManifestStatus` of IAS API versions 3 and 4. IAS's own `UNKNOWN` status is
+/// `Unknown("UNKNOWN")`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(from = "String")]
+pub enum PseManifestStatus {
+    Ok,
+    Invalid,
+    OutOfDate,
+    Revoked,
+    RlVersionMismatch,
+    /// Status not known to this version of the SP
+    Unknown(String),
+}
+
+impl PseManifestStatus {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Ok => "OK",
+            Self::Invalid => "INVALID",
+            Self::OutOfDate => "OUT_OF_DATE",
+            Self::Revoked => "REVOKED",
+            Self::RlVersionMismatch => "RL_VERSION_MISMATCH",
+            Self::Unknown(status) => status,
+        }
+    }
+}
+
+impl From<&str> for PseManifestStatus {
+    fn from(status: &str) -> Self {
+        match status {
+            "OK" => Self::Ok,
+            "INVALID" => Self::Invalid,
+            "OUT_OF_DATE" => Self::OutOfDate,
+            "REVOKED" => Self::Revoked,
+            "RL_VERSION_MISMATCH" => Self::RlVersionMismatch,
+            status => Self::Unknown(status.to_owned()),
+        }
+    }
+}
+
+impl From<String> for PseManifestStatus {
+    fn from(status: String) -> Self {
+        Self::from(status.as_str())
+    }
+}
+
+impl fmt::Display for PseManifestStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
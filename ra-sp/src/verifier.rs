@@ -0,0 +1,21 @@
+use crate::attestation_response::AttestationResponse;
+use crate::error::IasError;
+use async_trait::async_trait;
+use ra_common::msg::{Gid, Quote, QuoteNonce};
+
+/// Backend used by the SP to obtain the signature revocation list of an EPID group and to
+/// verify quote evidence. `IasClient` implements this trait for Intel Attestation Service v3/v4;
+/// other implementations may be used for testing, caching or alternate attestation services.
+#[async_trait]
+pub trait AttestationVerifier: Send + Sync {
+    /// Return the SigRL of the given EPID group, or `None` if it is empty.
+    async fn get_sig_rl(&self, gid: &Gid) -> Result<Option<Vec<u8>>, IasError>;
+
+    /// Verify the quote and return the verified attestation report. If `nonce` is given, the
+    /// report must carry it, hex-encoded, in its `nonce` field.
+    async fn verify_attestation_evidence(
+        &self,
+        quote: &Quote,
+        nonce: Option<&QuoteNonce>,
+    ) -> Result<AttestationResponse, IasError>;
+}
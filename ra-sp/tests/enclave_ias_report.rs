@@ -0,0 +1,134 @@
+//! The enclave's own check of an IAS report forwarded in MSG4, against reports signed by
+//! `MockIas`'s test CA.
+use ra_common::msg::{IasReport, Quote, QuoteNonce, QUOTE_BODY_LEN};
+use ra_enclave::ias_report::verify_ias_report;
+use ra_enclave::IasReportError;
+use ra_sp::mock_ias::{MockIas, MockIasBehavior};
+use ra_sp::{AttestationVerifier, IasApiVersion};
+
+const NONCE: QuoteNonce = [7u8; 16];
+
+fn quote() -> Quote {
+    let mut quote = vec![0u8; QUOTE_BODY_LEN + 4];
+    quote[0] = 2;
+    quote[QUOTE_BODY_LEN - 1] = 0xaa;
+    quote
+}
+
+/// Report of `quote()` as the SP would forward it.
+async fn signed_report(ias: &MockIas, nonce: Option<&QuoteNonce>) -> IasReport {
+    let client = ias.ias_client(IasApiVersion::V4).unwrap();
+    let response = client
+        .verify_attestation_evidence(&quote(), nonce)
+        .await
+        .unwrap();
+    response.ias_report.unwrap()
+}
+
+fn verify(
+    ias: &MockIas,
+    report: &IasReport,
+    quote: &Quote,
+    nonce: &QuoteNonce,
+    require_nonce: bool,
+) -> Result<String, IasReportError> {
+    let mut root_ca_cert = ias.root_ca_cert().unwrap();
+    verify_ias_report(&mut root_ca_cert, report, quote, nonce, require_nonce)
+}
+
+#[tokio::test]
+async fn accepts_valid_report() {
+    let ias = MockIas::start(MockIasBehavior::default()).unwrap();
+    let report = signed_report(&ias, Some(&NONCE)).await;
+
+    let quote_status = verify(&ias, &report, &quote(), &NONCE, true).unwrap();
+    assert_eq!(quote_status, "OK");
+}
+
+#[tokio::test]
+async fn returns_untrusted_quote_status() {
+    let ias = MockIas::start(MockIasBehavior {
+        isv_enclave_quote_status: "GROUP_REVOKED".to_owned(),
+        ..Default::default()
+    })
+    .unwrap();
+    let report = signed_report(&ias, Some(&NONCE)).await;
+
+    let quote_status = verify(&ias, &report, &quote(), &NONCE, true).unwrap();
+    assert_eq!(quote_status, "GROUP_REVOKED");
+}
+
+#[tokio::test]
+async fn rejects_report_of_other_ca() {
+    let ias = MockIas::start(MockIasBehavior::default()).unwrap();
+    let other_ias = MockIas::start(MockIasBehavior::default()).unwrap();
+    let report = signed_report(&ias, Some(&NONCE)).await;
+
+    match verify(&other_ias, &report, &quote(), &NONCE, true) {
+        Err(IasReportError::InvalidCertificate) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn rejects_tampered_body_or_signature() {
+    let ias = MockIas::start(MockIasBehavior::default()).unwrap();
+    let report = signed_report(&ias, Some(&NONCE)).await;
+
+    let mut tampered_body = report.clone();
+    let body = String::from_utf8(tampered_body.body).unwrap();
+    tampered_body.body = body.replace("\"OK\"", "\"KO\"").into_bytes();
+    assert_ne!(tampered_body.body, report.body);
+    match verify(&ias, &tampered_body, &quote(), &NONCE, true) {
+        Err(IasReportError::BadSignature) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    let mut tampered_signature = report;
+    tampered_signature.signature[0] ^= 0xff;
+    match verify(&ias, &tampered_signature, &quote(), &NONCE, true) {
+        Err(IasReportError::BadSignature) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn rejects_report_of_other_quote() {
+    let ias = MockIas::start(MockIasBehavior::default()).unwrap();
+    let report = signed_report(&ias, Some(&NONCE)).await;
+
+    let mut other_quote = quote();
+    other_quote[QUOTE_BODY_LEN - 1] ^= 0xff;
+    match verify(&ias, &report, &other_quote, &NONCE, true) {
+        Err(IasReportError::QuoteMismatched) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn rejects_other_nonce() {
+    let ias = MockIas::start(MockIasBehavior::default()).unwrap();
+    let report = signed_report(&ias, Some(&NONCE)).await;
+
+    // A nonce in the report is checked even if it isn't required
+    let other_nonce = [8u8; 16];
+    match verify(&ias, &report, &quote(), &other_nonce, false) {
+        Err(IasReportError::NonceMismatched) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn rejects_missing_nonce_only_if_required() {
+    let ias = MockIas::start(MockIasBehavior::default()).unwrap();
+    let report = signed_report(&ias, None).await;
+
+    match verify(&ias, &report, &quote(), &NONCE, true) {
+        Err(IasReportError::MissingNonce) => {}
+        r => panic!("unexpected result: {:?}", r),
+    }
+    assert_eq!(
+        verify(&ias, &report, &quote(), &NONCE, false).unwrap(),
+        "OK"
+    );
+}
@@ -0,0 +1,263 @@
+//! `IasClient` against `MockIas`: the generated CA chain and signatures are accepted, advisories
+//! are read from the headers (v3) or the signed body (v4), failures map to typed errors, and a
+//! rejected primary subscription key fails over to the secondary one.
+use hyper::StatusCode;
+use ra_common::msg::{Quote, QUOTE_BODY_LEN};
+use ra_sp::mock_ias::{
+    MockIas, MockIasBehavior, PRIMARY_SUBSCRIPTION_KEY, SECONDARY_SUBSCRIPTION_KEY,
+};
+use ra_sp::{
+    AttestationError, AttestationVerifier, IasApiVersion, IasError, QuoteStatus, SubscriptionKey,
+};
+use serde_json::Value;
+
+const GID: [u8; 4] = [0x00, 0x00, 0x0b, 0x5a];
+
+fn quote() -> Quote {
+    let mut quote = vec![0u8; QUOTE_BODY_LEN + 4];
+    quote[0] = 2;
+    quote[QUOTE_BODY_LEN - 1] = 0xaa;
+    quote
+}
+
+fn key_status(statuses: &[(&str, StatusCode)]) -> MockIasBehavior {
+    MockIasBehavior {
+        subscription_key_status: statuses
+            .iter()
+            .map(|&(key, status)| (key.to_owned(), status))
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn advisories() -> MockIasBehavior {
+    MockIasBehavior {
+        isv_enclave_quote_status: "SW_HARDENING_NEEDED".to_owned(),
+        advisory_url: Some("https://security-center.intel.com".to_owned()),
+        advisory_ids: vec!["INTEL-SA-00334".to_owned(), "INTEL-SA-00615".to_owned()],
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn accepts_mock_report() {
+    let ias = MockIas::start(MockIasBehavior::default()).unwrap();
+    let client = ias.ias_client(IasApiVersion::V3).unwrap();
+    let quote = quote();
+    let nonce = [7u8; 16];
+
+    let response = client
+        .verify_attestation_evidence(&quote, Some(&nonce))
+        .await
+        .unwrap();
+    assert_eq!(response.isv_enclave_quote_status, QuoteStatus::Ok);
+    assert_eq!(
+        response.isv_enclave_quote_body,
+        base64::encode(&quote[..QUOTE_BODY_LEN])
+    );
+    assert_eq!(response.nonce, Some(hex::encode(&nonce)));
+    assert_eq!(response.version, 3);
+    assert_eq!(response.subscription_key, Some(SubscriptionKey::Primary));
+    assert!(response.advisory_ids.is_empty());
+    let ias_report = response.ias_report.unwrap();
+    assert!(ias_report.certificate_chain.contains("BEGIN CERTIFICATE"));
+    assert!(!ias_report.signature.is_empty());
+}
+
+#[tokio::test]
+async fn reads_v3_advisories_from_headers() {
+    let ias = MockIas::start(advisories()).unwrap();
+    let client = ias.ias_client(IasApiVersion::V3).unwrap();
+
+    let response = client
+        .verify_attestation_evidence(&quote(), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        response.isv_enclave_quote_status,
+        QuoteStatus::SwHardeningNeeded
+    );
+    assert_eq!(response.advisory_ids, advisories().advisory_ids);
+    assert_eq!(response.advisory_url, advisories().advisory_url);
+    let body: Value = serde_json::from_slice(&response.ias_report.unwrap().body[..]).unwrap();
+    assert!(body.get("advisoryIDs").is_none());
+}
+
+#[tokio::test]
+async fn reads_v4_advisories_from_signed_body() {
+    let ias = MockIas::start(advisories()).unwrap();
+    let client = ias.ias_client(IasApiVersion::V4).unwrap();
+
+    let response = client
+        .verify_attestation_evidence(&quote(), None)
+        .await
+        .unwrap();
+    assert_eq!(response.version, 4);
+    assert_eq!(response.advisory_ids, advisories().advisory_ids);
+    let body: Value = serde_json::from_slice(&response.ias_report.unwrap().body[..]).unwrap();
+    assert_eq!(body["advisoryIDs"][1], "INTEL-SA-00615");
+}
+
+#[tokio::test]
+async fn rejects_bad_signature() {
+    let ias = MockIas::start(MockIasBehavior {
+        bad_signature: true,
+        ..Default::default()
+    })
+    .unwrap();
+    let client = ias.ias_client(IasApiVersion::V3).unwrap();
+
+    match client.verify_attestation_evidence(&quote(), None).await {
+        Err(IasError::Attestation(AttestationError::BadSignature)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn rejects_report_of_other_ca() {
+    let ias = MockIas::start(MockIasBehavior::default()).unwrap();
+    let other_ias = MockIas::start(MockIasBehavior::default()).unwrap();
+    let client = ra_sp::IasClient::new(
+        other_ias.root_ca_cert().unwrap(),
+        "mock-primary-subscription-key".to_owned(),
+        None,
+        ias.base_uri(),
+        IasApiVersion::V3,
+    );
+
+    match client.verify_attestation_evidence(&quote(), None).await {
+        Err(IasError::Attestation(AttestationError::MismatchedIASRootCertificate)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn reports_error_status() {
+    let ias = MockIas::start(MockIasBehavior {
+        report_status: StatusCode::BAD_REQUEST,
+        sig_rl_status: StatusCode::NOT_FOUND,
+        ..Default::default()
+    })
+    .unwrap();
+    let client = ias.ias_client(IasApiVersion::V3).unwrap();
+
+    match client.verify_attestation_evidence(&quote(), None).await {
+        Err(IasError::Attestation(AttestationError::Connection(StatusCode::BAD_REQUEST))) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    match client.get_sig_rl(&GID).await {
+        Err(IasError::SigRLError(StatusCode::NOT_FOUND)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn returns_sig_rl() {
+    let ias = MockIas::start(MockIasBehavior::default()).unwrap();
+    let client = ias.ias_client(IasApiVersion::V3).unwrap();
+    assert_eq!(client.get_sig_rl(&GID).await.unwrap(), None);
+
+    ias.set_behavior(MockIasBehavior {
+        sig_rl: Some(vec![1, 2, 3]),
+        ..Default::default()
+    });
+    assert_eq!(client.get_sig_rl(&GID).await.unwrap(), Some(vec![1, 2, 3]));
+}
+
+#[tokio::test]
+async fn fails_over_to_secondary_key() {
+    for &status in [
+        StatusCode::UNAUTHORIZED,
+        StatusCode::FORBIDDEN,
+        StatusCode::TOO_MANY_REQUESTS,
+    ]
+    .iter()
+    {
+        let ias = MockIas::start(key_status(&[(PRIMARY_SUBSCRIPTION_KEY, status)])).unwrap();
+        let client = ias
+            .ias_client_with_secondary_key(IasApiVersion::V3)
+            .unwrap();
+
+        assert_eq!(client.get_sig_rl(&GID).await.unwrap(), None);
+        let response = client
+            .verify_attestation_evidence(&quote(), None)
+            .await
+            .unwrap();
+        assert_eq!(response.subscription_key, Some(SubscriptionKey::Secondary));
+        assert_eq!(
+            ias.subscription_keys(),
+            [
+                PRIMARY_SUBSCRIPTION_KEY,
+                SECONDARY_SUBSCRIPTION_KEY,
+                PRIMARY_SUBSCRIPTION_KEY,
+                SECONDARY_SUBSCRIPTION_KEY
+            ],
+            "status {}",
+            status
+        );
+    }
+}
+
+#[tokio::test]
+async fn keeps_primary_key_on_other_errors() {
+    let ias = MockIas::start(MockIasBehavior {
+        report_status: StatusCode::BAD_REQUEST,
+        ..Default::default()
+    })
+    .unwrap();
+    let client = ias
+        .ias_client_with_secondary_key(IasApiVersion::V3)
+        .unwrap();
+
+    match client.verify_attestation_evidence(&quote(), None).await {
+        Err(IasError::Attestation(AttestationError::Connection(StatusCode::BAD_REQUEST))) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    assert_eq!(ias.subscription_keys(), [PRIMARY_SUBSCRIPTION_KEY]);
+}
+
+#[tokio::test]
+async fn does_not_retry_without_secondary_key() {
+    let ias = MockIas::start(key_status(&[(
+        PRIMARY_SUBSCRIPTION_KEY,
+        StatusCode::UNAUTHORIZED,
+    )]))
+    .unwrap();
+    let client = ias.ias_client(IasApiVersion::V3).unwrap();
+
+    match client.verify_attestation_evidence(&quote(), None).await {
+        Err(IasError::Attestation(AttestationError::Connection(StatusCode::UNAUTHORIZED))) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    assert_eq!(ias.subscription_keys(), [PRIMARY_SUBSCRIPTION_KEY]);
+}
+
+#[tokio::test]
+async fn reports_error_when_both_keys_fail() {
+    let ias = MockIas::start(key_status(&[
+        (PRIMARY_SUBSCRIPTION_KEY, StatusCode::TOO_MANY_REQUESTS),
+        (SECONDARY_SUBSCRIPTION_KEY, StatusCode::FORBIDDEN),
+    ]))
+    .unwrap();
+    let client = ias
+        .ias_client_with_secondary_key(IasApiVersion::V3)
+        .unwrap();
+
+    match client.verify_attestation_evidence(&quote(), None).await {
+        Err(IasError::Attestation(AttestationError::Connection(StatusCode::FORBIDDEN))) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    match client.get_sig_rl(&GID).await {
+        Err(IasError::SigRLError(StatusCode::FORBIDDEN)) => {}
+        result => panic!("unexpected result: {:?}", result),
+    }
+    assert_eq!(
+        ias.subscription_keys(),
+        [
+            PRIMARY_SUBSCRIPTION_KEY,
+            SECONDARY_SUBSCRIPTION_KEY,
+            PRIMARY_SUBSCRIPTION_KEY,
+            SECONDARY_SUBSCRIPTION_KEY
+        ]
+    );
+}
@@ -0,0 +1,161 @@
+//! The whole MSG0–MSG4 flow with the simulated enclave and client, and the SP verifying quotes with
+//! `MockIas`, over loopback TCP streams.
+use ra_client::{ClientRaContext, ClientRaError};
+use ra_common::sim::SIM_ENCLAVE_MRENCLAVE;
+use ra_enclave::{EnclaveRaContext, EnclaveRaError, IasReportError};
+use ra_sp::mock_ias::{MockIas, MockIasBehavior};
+use ra_sp::{IasApiVersion, QuoteStatus, SpConfig, SpRaContext, SpRaError};
+use serde_json::json;
+use sgx_crypto::certificate::X509Cert;
+use sgx_crypto::cmac::MacTag;
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+const SP_KEYS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/data/sp-keys");
+
+fn stream_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (peer, _) = listener.accept().unwrap();
+    (stream, peer)
+}
+
+fn sp_config() -> SpConfig {
+    serde_json::from_value(json!({
+        "debug": false,
+        "linkable": false,
+        "random_nonce": true,
+        "use_platform_service": false,
+        "spid": hex::encode([0x5au8; 16]),
+        "primary_subscription_key": "mock-primary-subscription-key",
+        "secondary_subscription_key": "",
+        "quote_trust_options": [],
+        "sp_private_key_pem_path": format!("{}/private_key.pem", SP_KEYS_DIR),
+        "ias_root_cert_pem_path": "",
+        "sigstruct_path": "",
+        "identity_policy": {
+            "rules": [{ "name": "sim", "mrenclave": hex::encode(SIM_ENCLAVE_MRENCLAVE) }]
+        }
+    }))
+    .unwrap()
+}
+
+fn sp_vkey_pem() -> String {
+    let mut pem = std::fs::read_to_string(format!("{}/public_key.pem", SP_KEYS_DIR)).unwrap();
+    pem.push('\0');
+    pem
+}
+
+type AttestationResults = (
+    Result<(MacTag, MacTag), EnclaveRaError>,
+    Result<(), ClientRaError>,
+    Result<ra_sp::AttestationResult, SpRaError>,
+);
+
+fn attest(behavior: MockIasBehavior) -> AttestationResults {
+    attest_with(behavior, sp_config(), false)
+}
+
+/// Runs the enclave and the SP on their own threads and the client on this one, and returns the
+/// result of each role. With `verify_ias_report`, the enclave requires the IAS report and its
+/// nonce, verifies it against `MockIas`'s CA, and only trusts the "OK" status.
+fn attest_with(
+    behavior: MockIasBehavior,
+    sp_config: SpConfig,
+    verify_ias_report: bool,
+) -> AttestationResults {
+    let ias = MockIas::start(behavior).unwrap();
+    let ias_client = ias.ias_client(IasApiVersion::V4).unwrap();
+    let ias_root_cert_pem = if verify_ias_report {
+        Some(format!("{}\0", ias.root_ca_cert_pem()))
+    } else {
+        None
+    };
+    let (mut enclave_stream, mut client_enclave_stream) = stream_pair();
+    let (mut sp_stream, mut client_sp_stream) = stream_pair();
+
+    // The contexts hold RNGs, so they are created on the threads that use them
+    let enclave = thread::spawn(move || {
+        let mut context = EnclaveRaContext::init(&sp_vkey_pem()).unwrap();
+        if let Some(pem) = ias_root_cert_pem {
+            context.ias_root_cert = Some(X509Cert::new_from_pem(pem.as_bytes()).unwrap());
+            context.require_ias_nonce = true;
+        }
+        context.do_attestation(&mut enclave_stream)
+    });
+    let sp = thread::spawn(move || {
+        let context = SpRaContext::init(sp_config, ias_client).unwrap();
+        context.do_attestation(&mut sp_stream)
+    });
+    let client_context = ClientRaContext::init().unwrap();
+    let client_result =
+        client_context.do_attestation(&mut client_enclave_stream, &mut client_sp_stream);
+    (enclave.join().unwrap(), client_result, sp.join().unwrap())
+}
+
+#[test]
+fn trusted_enclave_shares_keys_with_sp() {
+    let (enclave_result, client_result, sp_result) = attest(MockIasBehavior::default());
+
+    let (signing_key, master_key) = enclave_result.unwrap();
+    client_result.unwrap();
+    let sp_result = sp_result.unwrap();
+    assert_eq!(sp_result.matched_rule, "sim");
+    assert_eq!(sp_result.report_body.mrenclave, SIM_ENCLAVE_MRENCLAVE);
+    assert_eq!(sp_result.signing_key, signing_key);
+    assert_eq!(sp_result.master_key, master_key);
+}
+
+#[test]
+fn revoked_enclave_is_rejected_by_all_roles() {
+    let (enclave_result, client_result, sp_result) = attest(MockIasBehavior {
+        isv_enclave_quote_status: "GROUP_REVOKED".to_owned(),
+        ..Default::default()
+    });
+
+    match enclave_result {
+        Err(EnclaveRaError::EnclaveNotTrusted) => {}
+        result => panic!("unexpected enclave result: {:?}", result),
+    }
+    match client_result {
+        Err(ClientRaError::EnclaveNotTrusted) => {}
+        result => panic!("unexpected client result: {:?}", result),
+    }
+    match sp_result {
+        Err(SpRaError::EnclaveNotTrusted) => {}
+        result => panic!("unexpected SP result: {:?}", result.map(|r| r.matched_rule)),
+    }
+}
+
+#[test]
+fn enclave_verifies_forwarded_report() {
+    let mut sp_config = sp_config();
+    sp_config.forward_ias_report = true;
+    let (enclave_result, client_result, sp_result) =
+        attest_with(MockIasBehavior::default(), sp_config, true);
+
+    let (_, master_key) = enclave_result.unwrap();
+    client_result.unwrap();
+    assert_eq!(sp_result.unwrap().master_key, master_key);
+}
+
+#[test]
+fn enclave_rejects_status_trusted_only_by_sp() {
+    let mut sp_config = sp_config();
+    sp_config.forward_ias_report = true;
+    sp_config.quote_trust_options = vec![QuoteStatus::GroupOutOfDate];
+    let behavior = MockIasBehavior {
+        isv_enclave_quote_status: "GROUP_OUT_OF_DATE".to_owned(),
+        ..Default::default()
+    };
+    let (enclave_result, client_result, sp_result) = attest_with(behavior, sp_config, true);
+
+    match enclave_result {
+        Err(EnclaveRaError::IasReport(IasReportError::QuoteStatusNotTrusted(status))) => {
+            assert_eq!(status, "GROUP_OUT_OF_DATE")
+        }
+        result => panic!("unexpected enclave result: {:?}", result),
+    }
+    client_result.unwrap();
+    sp_result.unwrap();
+}
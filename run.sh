@@ -2,11 +2,16 @@ TARGET_NAME=tls-enclave
 TARGET_DIR=ra-enclave/target/x86_64-fortanix-unknown-sgx/debug/examples
 TARGET=$TARGET_DIR/$TARGET_NAME.sgxs
 
-# Run enclave with the default runner
-ftxsgx-runner --signature coresident $TARGET &
+# Run SP
+(cd ra-sp && cargo run -Zfeatures=itarget --example tls-sp --features "verbose") &
+SP_PID=$!
 
-# Run client
-(cd ra-client && cargo run -Zfeatures=itarget --example tls-client --features verbose) &
+# Run client relay, which the enclave connects to and which connects to SP
+ra-client/target/debug/ra-client --config ra-client/examples/data/relay.json &
+RELAY_PID=$!
 
-# Run SP
-(cd ra-sp && cargo run -Zfeatures=itarget --example tls-sp --features "verbose")
+# Run enclave with the default runner
+ftxsgx-runner --signature coresident $TARGET
+
+wait $SP_PID
+kill $RELAY_PID
@@ -1,6 +1,6 @@
 #[cfg(target_env = "sgx")]
 mod inner {
-    use mbedtls::rng::Rdrand;
+    use mbedtls::rng::{Random, Rdrand};
     pub struct Rng {
         pub inner: Rdrand,
     }
@@ -9,12 +9,17 @@ mod inner {
         pub fn new() -> Self {
             Self { inner: Rdrand }
         }
+
+        pub fn fill_bytes(&mut self, buf: &mut [u8]) -> super::super::Result<()> {
+            self.inner.random(buf)?;
+            Ok(())
+        }
     }
 }
 
 #[cfg(not(target_env = "sgx"))]
 mod inner {
-    use mbedtls::rng::OsEntropy;
+    use mbedtls::rng::{OsEntropy, Random};
     use std::pin::Pin;
     pub struct Rng<'a> {
         pub inner: mbedtls::rng::CtrDrbg<'a>,
@@ -32,6 +37,11 @@ mod inner {
                 })
             }
         }
+
+        pub fn fill_bytes(&mut self, buf: &mut [u8]) -> super::super::Result<()> {
+            self.inner.random(buf)?;
+            Ok(())
+        }
     }
 }
 